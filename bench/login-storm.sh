@@ -0,0 +1,51 @@
+#!/bin/sh
+# Measures latency of `GET /user/get` while the service is flooded with logins.
+# Password hashing runs on a bounded blocking pool, so reads must stay fast
+# and excess logins must be rejected with 503 instead of queueing forever.
+#
+# Requires running application and `oha` (cargo install oha)
+
+set -e
+
+HOST=${HOST:-http://localhost:8080}
+DURATION=${DURATION:-30s}
+CONNECTIONS=${CONNECTIONS:-64}
+LOGIN_CONNECTIONS=${LOGIN_CONNECTIONS:-256}
+LOGIN="bench_$(date +%s%N)"
+
+USER_ID=$(curl -sf -X POST "$HOST/user/register" \
+    -H "Content-Type: application/json" \
+    -d "{
+      \"credentials\": { \"login\": \"$LOGIN\", \"password\": \"123456\" },
+      \"email\": \"$LOGIN@bench.local\",
+      \"first_name\": \"Bench\",
+      \"last_name\": \"Mark\",
+      \"birth_date\": \"1980-02-12\",
+      \"gender\": \"Male\",
+      \"interests\": [],
+      \"city\": \"N\"
+    }" | sed -E 's/.*"id":"([^"]+)".*/\1/')
+
+SESSION_ID=$(curl -sf -X POST "$HOST/login" \
+    -H "Content-Type: application/json" \
+    -d "{ \"credentials\": { \"login\": \"$LOGIN\", \"password\": \"123456\" } }" \
+    | sed -E 's/.*"session_id":"([^"]+)".*/\1/')
+
+echo "### GET /user/get without logins"
+oha -z "$DURATION" -c "$CONNECTIONS" --no-tui \
+    -H "Authorization: session-id $SESSION_ID" \
+    "$HOST/user/get/$USER_ID"
+
+echo "### POST /login storm"
+oha -z "$DURATION" -c "$LOGIN_CONNECTIONS" --no-tui \
+    -m POST -H "Content-Type: application/json" \
+    -d "{ \"credentials\": { \"login\": \"$LOGIN\", \"password\": \"123456\" } }" \
+    "$HOST/login" &
+STORM=$!
+
+echo "### GET /user/get during login storm"
+oha -z "$DURATION" -c "$CONNECTIONS" --no-tui \
+    -H "Authorization: session-id $SESSION_ID" \
+    "$HOST/user/get/$USER_ID"
+
+wait $STORM
@@ -0,0 +1,95 @@
+#!/bin/sh
+# Checks that failed logins don't reveal whether the login exists.
+# Requests for an existing and an unknown login are interleaved, so drift of the host load
+# affects both samples equally. Samples are compared with Welch's t-test, the script exits
+# with code 1 when |t| exceeds T_THRESHOLD (4.5, the threshold used by dudect).
+#
+# Unknown logins are verified against a dummy hash of the configured algorithm. Accounts
+# still holding a hash of an outdated algorithm or parameters are verified at that cost
+# until the next successful login upgrades it. Set LEGACY_LOGIN to such an account
+# (registered while the application ran with another `password_hasher`) to measure
+# the residual difference, it is reported but doesn't fail the check.
+#
+# Lockout would reject most of the attempts, so the application has to be started
+# with relaxed thresholds: CONFIG=cfg/bench.yml cargo run --release
+#
+# Requires running application, `curl` and `awk`
+
+set -e
+
+HOST=${HOST:-http://localhost:8080}
+SAMPLES=${SAMPLES:-500}
+T_THRESHOLD=${T_THRESHOLD:-4.5}
+LOGIN="bench_$(date +%s%N)"
+UNKNOWN="unknown_$(date +%s%N)"
+SAMPLES_FILE=$(mktemp)
+trap 'rm -f "$SAMPLES_FILE"' EXIT
+
+curl -sf -X POST "$HOST/user/register" \
+    -H "Content-Type: application/json" \
+    -d "{
+      \"credentials\": { \"login\": \"$LOGIN\", \"password\": \"123456\" },
+      \"email\": \"$LOGIN@bench.local\",
+      \"first_name\": \"Bench\",
+      \"last_name\": \"Mark\",
+      \"birth_date\": \"1980-02-12\",
+      \"gender\": \"Male\",
+      \"interests\": [],
+      \"city\": \"N\"
+    }" > /dev/null
+
+login() {
+    curl -s -o /dev/null -w "$2\t%{time_total}\n" -X POST "$HOST/login" \
+        -H "Content-Type: application/json" \
+        -d "{ \"credentials\": { \"login\": \"$1\", \"password\": \"wrong_password\" } }"
+}
+
+# Warm up connections and the hashing pool
+for _ in 1 2 3 4 5; do
+    login "$UNKNOWN" "warmup" > /dev/null
+done
+
+i=0
+while [ "$i" -lt "$SAMPLES" ]; do
+    login "$LOGIN" "existing"
+    login "$UNKNOWN" "unknown"
+    if [ -n "$LEGACY_LOGIN" ]; then
+        login "$LEGACY_LOGIN" "legacy"
+    fi
+    i=$((i + 1))
+done > "$SAMPLES_FILE"
+
+awk -F '\t' -v threshold="$T_THRESHOLD" '
+    {
+        ms = $2 * 1000
+        n[$1]++
+        sum[$1] += ms
+        sq[$1] += ms * ms
+    }
+
+    function mean(name) { return sum[name] / n[name] }
+    function var(name) { return (sq[name] - sum[name] * sum[name] / n[name]) / (n[name] - 1) }
+    function welch(left, right) {
+        return (mean(left) - mean(right)) / sqrt(var(left) / n[left] + var(right) / n[right])
+    }
+    function abs(value) { return value < 0 ? -value : value }
+
+    END {
+        printf "login\tsamples\tavg_ms\tstddev_ms\n"
+        for (name in n) {
+            printf "%s\t%d\t%.2f\t%.2f\n", name, n[name], mean(name), sqrt(var(name))
+        }
+
+        t = welch("existing", "unknown")
+        printf "\nexisting vs unknown: t = %.2f, threshold %.2f\n", t, threshold
+        if ("legacy" in n) {
+            printf "legacy vs unknown: t = %.2f (residual, not checked)\n", welch("legacy", "unknown")
+        }
+
+        if (abs(t) > threshold) {
+            print "FAIL: existing and unknown logins are distinguishable by latency"
+            exit 1
+        }
+        print "OK: distributions are indistinguishable"
+    }
+' "$SAMPLES_FILE"
@@ -0,0 +1,37 @@
+#!/bin/sh
+# Measures latency of an authenticated endpoint. Every request after the first one
+# is served from the session cache and must not touch the database pool.
+#
+# Requires running application and `oha` (cargo install oha)
+
+set -e
+
+HOST=${HOST:-http://localhost:8080}
+DURATION=${DURATION:-30s}
+CONNECTIONS=${CONNECTIONS:-64}
+LOGIN="bench_$(date +%s%N)"
+
+USER_ID=$(curl -sf -X POST "$HOST/user/register" \
+    -H "Content-Type: application/json" \
+    -d "{
+      \"credentials\": { \"login\": \"$LOGIN\", \"password\": \"123456\" },
+      \"email\": \"$LOGIN@bench.local\",
+      \"first_name\": \"Bench\",
+      \"last_name\": \"Mark\",
+      \"birth_date\": \"1980-02-12\",
+      \"gender\": \"Male\",
+      \"interests\": [],
+      \"city\": \"N\"
+    }" | sed -E 's/.*"id":"([^"]+)".*/\1/')
+
+SESSION_ID=$(curl -sf -X POST "$HOST/login" \
+    -H "Content-Type: application/json" \
+    -d "{ \"credentials\": { \"login\": \"$LOGIN\", \"password\": \"123456\" } }" \
+    | sed -E 's/.*"session_id":"([^"]+)".*/\1/')
+
+oha -z "$DURATION" -c "$CONNECTIONS" --no-tui \
+    -H "Authorization: session-id $SESSION_ID" \
+    "$HOST/user/get/$USER_ID"
+
+curl -sf "$HOST/metrics/session-cache"
+echo
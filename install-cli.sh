@@ -2,3 +2,4 @@
 
 cargo install sqlx-cli
 cargo install refinery_cli
+cargo install oha
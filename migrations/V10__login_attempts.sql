@@ -0,0 +1,6 @@
+CREATE TABLE login_attempts (
+    key varchar PRIMARY KEY,
+    failures integer NOT NULL,
+    last_failure timestamp NOT NULL,
+    locked_until timestamp
+);
@@ -0,0 +1,6 @@
+CREATE TABLE revoked_tokens (
+    jti varchar PRIMARY KEY,
+    expires timestamp NOT NULL
+);
+
+CREATE INDEX idx_revoked_tokens_expires ON revoked_tokens (expires);
@@ -0,0 +1,16 @@
+CREATE TABLE refresh_tokens (
+    token_hash varchar PRIMARY KEY,
+    family_id uuid NOT NULL,
+    user_id uuid REFERENCES users(id) NOT NULL,
+    expires timestamp NOT NULL,
+    used boolean NOT NULL DEFAULT false
+);
+
+CREATE INDEX idx_refresh_tokens_family_id ON refresh_tokens (family_id);
+CREATE INDEX idx_refresh_tokens_user_id ON refresh_tokens (user_id);
+CREATE INDEX idx_refresh_tokens_expires ON refresh_tokens (expires);
+
+ALTER TABLE sessions
+ADD COLUMN family_id uuid;
+
+CREATE INDEX idx_sessions_family_id ON sessions (family_id);
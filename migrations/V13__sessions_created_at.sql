@@ -0,0 +1,2 @@
+ALTER TABLE sessions
+ADD COLUMN created_at timestamp NOT NULL DEFAULT (now() AT TIME ZONE 'utc');
@@ -0,0 +1,5 @@
+UPDATE sessions
+SET session_id = encode(sha256(convert_to(session_id, 'UTF8')), 'hex');
+
+UPDATE revoked_tokens
+SET jti = encode(sha256(convert_to(jti, 'UTF8')), 'hex');
@@ -0,0 +1,4 @@
+ALTER TABLE sessions
+ADD COLUMN last_seen timestamp NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+ADD COLUMN user_agent varchar,
+ADD COLUMN client_ip varchar;
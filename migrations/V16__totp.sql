@@ -0,0 +1,21 @@
+CREATE TABLE totp_credentials (
+    user_id uuid PRIMARY KEY REFERENCES users(id),
+    secret varchar NOT NULL,
+    confirmed boolean NOT NULL DEFAULT false,
+    last_used_step bigint
+);
+
+CREATE TABLE totp_recovery_codes (
+    code_hash varchar PRIMARY KEY,
+    user_id uuid REFERENCES users(id) NOT NULL
+);
+
+CREATE INDEX idx_totp_recovery_codes_user_id ON totp_recovery_codes (user_id);
+
+CREATE TABLE login_challenges (
+    challenge_hash varchar PRIMARY KEY,
+    user_id uuid REFERENCES users(id) NOT NULL,
+    expires timestamp NOT NULL
+);
+
+CREATE INDEX idx_login_challenges_user_id ON login_challenges (user_id);
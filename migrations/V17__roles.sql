@@ -0,0 +1,18 @@
+CREATE TABLE roles (
+    name varchar PRIMARY KEY
+);
+
+CREATE TABLE role_permissions (
+    role varchar REFERENCES roles(name) NOT NULL,
+    permission varchar NOT NULL,
+    PRIMARY KEY (role, permission)
+);
+
+CREATE TABLE user_roles (
+    user_id uuid REFERENCES users(id) NOT NULL,
+    role varchar REFERENCES roles(name) NOT NULL,
+    PRIMARY KEY (user_id, role)
+);
+
+INSERT INTO roles(name) VALUES ('admin');
+INSERT INTO role_permissions(role, permission) VALUES ('admin', 'users:admin');
@@ -0,0 +1,13 @@
+ALTER TABLE auth
+ADD COLUMN email varchar,
+ADD COLUMN state varchar(8) NOT NULL DEFAULT 'active';
+
+CREATE UNIQUE INDEX unq_auth_email ON auth (lower(email));
+
+CREATE TABLE email_verification_tokens (
+    token_hash varchar PRIMARY KEY,
+    user_id uuid REFERENCES users(id) NOT NULL,
+    expires timestamp NOT NULL
+);
+
+CREATE INDEX idx_email_verification_tokens_user_id ON email_verification_tokens (user_id);
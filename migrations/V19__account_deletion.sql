@@ -0,0 +1,44 @@
+ALTER TABLE users
+ADD COLUMN deleted_at timestamp;
+
+CREATE INDEX idx_users_deleted_at ON users (deleted_at) WHERE deleted_at IS NOT NULL;
+
+ALTER TABLE interest
+DROP CONSTRAINT interest_user_id_fkey,
+ADD CONSTRAINT interest_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE auth
+DROP CONSTRAINT auth_user_id_fkey,
+ADD CONSTRAINT auth_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE sessions
+DROP CONSTRAINT sessions_user_id_fkey,
+ADD CONSTRAINT sessions_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE password_reset_tokens
+DROP CONSTRAINT password_reset_tokens_user_id_fkey,
+ADD CONSTRAINT password_reset_tokens_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE refresh_tokens
+DROP CONSTRAINT refresh_tokens_user_id_fkey,
+ADD CONSTRAINT refresh_tokens_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE totp_credentials
+DROP CONSTRAINT totp_credentials_user_id_fkey,
+ADD CONSTRAINT totp_credentials_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE totp_recovery_codes
+DROP CONSTRAINT totp_recovery_codes_user_id_fkey,
+ADD CONSTRAINT totp_recovery_codes_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE login_challenges
+DROP CONSTRAINT login_challenges_user_id_fkey,
+ADD CONSTRAINT login_challenges_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE user_roles
+DROP CONSTRAINT user_roles_user_id_fkey,
+ADD CONSTRAINT user_roles_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE email_verification_tokens
+DROP CONSTRAINT email_verification_tokens_user_id_fkey,
+ADD CONSTRAINT email_verification_tokens_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
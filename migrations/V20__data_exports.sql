@@ -0,0 +1,10 @@
+CREATE TABLE data_exports (
+    id uuid PRIMARY KEY,
+    user_id uuid REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    status varchar(7) NOT NULL,
+    archive bytea,
+    expires timestamp NOT NULL
+);
+
+CREATE INDEX idx_data_exports_user_id ON data_exports (user_id);
+CREATE INDEX idx_data_exports_expires ON data_exports (expires);
@@ -0,0 +1,5 @@
+ALTER TABLE data_exports
+    ADD COLUMN attempts integer NOT NULL DEFAULT 0,
+    ADD COLUMN claimed_until timestamp;
+
+CREATE INDEX idx_data_exports_pending ON data_exports (claimed_until) WHERE status = 'pending';
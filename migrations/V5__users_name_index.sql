@@ -0,0 +1,2 @@
+CREATE INDEX idx_users_first_name_last_name
+ON users (first_name varchar_pattern_ops, last_name varchar_pattern_ops, id);
@@ -0,0 +1,4 @@
+ALTER TABLE sessions
+ADD COLUMN user_id uuid REFERENCES users(id);
+
+CREATE INDEX idx_sessions_user_id ON sessions (user_id);
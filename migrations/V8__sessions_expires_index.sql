@@ -0,0 +1 @@
+CREATE INDEX idx_sessions_expires ON sessions (expires);
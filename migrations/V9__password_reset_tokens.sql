@@ -0,0 +1,7 @@
+CREATE TABLE password_reset_tokens (
+    token_hash varchar PRIMARY KEY,
+    user_id uuid REFERENCES users(id) NOT NULL,
+    expires timestamp NOT NULL
+);
+
+CREATE INDEX idx_password_reset_tokens_user_id ON password_reset_tokens (user_id);
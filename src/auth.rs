@@ -1,32 +1,68 @@
-use crate::auth::AuthenticationError::{InternalError, InvalidSessionId, NoSessionIdHeader};
-use crate::config::AuthConfig;
+use crate::auth::AuthenticationError::{
+    CsrfTokenMismatch, InternalError, InvalidSessionId, MissingPermission, NoSessionIdHeader,
+};
+use crate::config::{AuthConfig, AuthorizationScheme, SessionMode};
 use async_trait::async_trait;
-use bcrypt::BcryptError;
 use chrono::{DateTime, Duration, Utc};
-use concurrent_queue::ConcurrentQueue;
-use dashmap::mapref::one::Ref as DashMapRef;
-use dashmap::DashMap;
-use log::{error, info};
+use log::{error, info, warn};
 use serde::Serialize;
 use std::fmt::Debug;
 use std::marker::PhantomData;
+use std::net::{IpAddr, SocketAddr};
 use std::ops::Add;
 use std::str::FromStr;
 use std::sync::Arc;
 use serde::ser::StdError;
 use thiserror::Error;
 use uuid::Uuid;
-use warp::http::header::AUTHORIZATION;
-use warp::http::StatusCode;
+use warp::http::header::{AUTHORIZATION, USER_AGENT};
+use warp::http::{HeaderMap, Method, StatusCode};
 use warp::reject::Reject;
 use warp::{reject, reply, Filter, Rejection, Reply};
 
 use crate::domain::protocol::ToReply;
-use crate::domain::user::{Credentials, User};
-use crate::extensions::Unit;
-use crate::pool::{DatabasePool, DbErrorOps};
-use crate::repo::auth_repository::AuthRepository;
-use crate::repo::session_repository::SessionRepository;
+use crate::domain::user::{Credentials, ExportError, User};
+use crate::notifier::{Notification, Notifier, Recipient};
+use crate::pool::{DatabasePool, DbErrorOps, TransactionOps};
+use crate::repo::auth_repository::{AccountState, AuthRepository, StoredCredentials};
+use crate::repo::session_repository::{RefreshToken, SessionRepository};
+use cookies::{SessionCookies, CSRF_HEADER, REFRESH_COOKIE, SESSION_COOKIE};
+use hashing_pool::{HashingPool, HashingPoolError};
+use jwt::{JwtCodec, JwtConfigError, RevokedTokens};
+use lockout::{LockoutKey, LockoutPolicy};
+use password_hasher::{PasswordHashError, PasswordHashers};
+use session_cache::{CachedSession, SessionCache, SessionCacheStats};
+use sliding::SlidingExpiration;
+
+pub(crate) mod cookies;
+pub(crate) mod forwarded;
+pub(crate) mod hashing_pool;
+pub(crate) mod jwt;
+pub(crate) mod lockout;
+pub(crate) mod password_hasher;
+pub(crate) mod revocation_listener;
+pub(crate) mod session_cache;
+pub(crate) mod sliding;
+pub(crate) mod tokens;
+pub(crate) mod totp;
+
+const REALM: &str = "social-network";
+
+/// Manage other users: their roles and sessions
+pub const USERS_ADMIN_PERMISSION: &str = "users:admin";
+
+/// Read operational metrics, such as session cache statistics
+pub const METRICS_READ_PERMISSION: &str = "metrics:read";
+
+/// `WWW-Authenticate` challenge, `invalid_token` is reported per RFC 6750 for rejected credentials
+pub fn challenge(scheme: AuthorizationScheme, invalid_token: bool) -> String {
+    match (scheme, invalid_token) {
+        (AuthorizationScheme::Bearer, true) => {
+            format!(r#"{} realm="{REALM}", error="invalid_token""#, scheme.name())
+        }
+        _ => format!(r#"{} realm="{REALM}""#, scheme.name()),
+    }
+}
 
 #[derive(Clone)]
 pub struct AuthenticationFilter<Pool, IDP>
@@ -36,6 +72,11 @@ where
 {
     pub pool: Arc<Pool>,
     pub idp: Arc<IDP>,
+    pub schemes: Vec<AuthorizationScheme>,
+    /// Present when session cookies are accepted
+    pub cookies: Option<SessionCookies>,
+    /// Peers whose forwarding headers are believed, see [forwarded::client_address]
+    pub trusted_proxies: Vec<IpAddr>,
 }
 
 impl<IDP, Pool> AuthenticationFilter<Pool, IDP>
@@ -43,63 +84,227 @@ where
     Pool: DatabasePool,
     IDP: IDPContext<Pool> + Sync,
 {
+    /// Token of an accepted scheme, scheme names are case-insensitive
+    fn token_from_header(&self, header: &str) -> Option<String> {
+        let (scheme, token) = header.split_once(' ')?;
+        let token = token.trim();
+
+        self.schemes
+            .iter()
+            .any(|accepted| accepted.name().eq_ignore_ascii_case(scheme))
+            .then(|| token.to_owned())
+            .filter(|token| !token.is_empty())
+    }
+
+    /// [AuthenticationFilter::with_session] which additionally requires the principal to hold `permission`
+    pub fn require_permission(
+        self: Arc<Self>,
+        permission: &'static str,
+    ) -> impl Filter<Extract = (Principal,), Error = Rejection> + Clone {
+        self.with_session().and_then(move |principal: Principal| async move {
+            if principal.has_permission(permission) {
+                Ok(principal)
+            } else {
+                warn!(user_id:display = principal.user_id, permission = permission; "Denied access");
+                Err(reject::custom(MissingPermission))
+            }
+        })
+    }
+
+    /// Client address is resolved through [AuthenticationFilter::trusted_proxies]
+    pub fn client_info(self: Arc<Self>) -> impl Filter<Extract = (ClientInfo,), Error = Rejection> + Clone {
+        warp::addr::remote()
+            .and(warp::header::headers_cloned())
+            .and(warp::header::optional(USER_AGENT.as_str()))
+            .map(move |remote: Option<SocketAddr>, headers: HeaderMap, user_agent: Option<String>| ClientInfo {
+                address: forwarded::client_address(
+                    remote.map(|address| address.ip()),
+                    &headers,
+                    &self.trusted_proxies,
+                ),
+                user_agent,
+            })
+    }
+
+    /// Header takes precedence over the cookie.
+    /// Cookie authenticated requests with unsafe methods have to pass the double-submit CSRF check
     pub fn with_session(
         self: Arc<Self>,
-    ) -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
-        warp::header::optional(AUTHORIZATION.as_str()).and_then(move |token: Option<String>| {
-            let inner_self = self.clone();
-            async move {
-                let session_id = token
-                    .and_then(|raw| {
-                        raw.strip_prefix("session-id ")
-                            .map(|reference| reference.to_owned())
-                    })
-                    .ok_or(reject::custom(NoSessionIdHeader))?;
-
-                match inner_self.pool.begin_tx().await {
-                    Err(err) => {
-                        error!(err:err = err; "Failed obtaining transaction for authentication");
-                        Err(reject::custom(InternalError))
+    ) -> impl Filter<Extract = (Principal,), Error = Rejection> + Clone {
+        warp::header::optional(AUTHORIZATION.as_str())
+            .and(warp::cookie::optional(SESSION_COOKIE))
+            .and(warp::header::optional(CSRF_HEADER))
+            .and(warp::method())
+            .and_then(
+                move |token: Option<String>,
+                      session_cookie: Option<String>,
+                      csrf_header: Option<String>,
+                      method: Method| {
+                    let inner_self = self.clone();
+                    async move {
+                        let session_id = match (token, session_cookie, &inner_self.cookies) {
+                            (Some(raw), _, _) => inner_self
+                                .token_from_header(&raw)
+                                .ok_or(reject::custom(NoSessionIdHeader))?,
+                            (None, Some(cookie), Some(cookies)) => {
+                                if !cookies.csrf_passed(&method, Some(&cookie), csrf_header.as_deref()) {
+                                    return Err(reject::custom(CsrfTokenMismatch));
+                                }
+                                cookie
+                            }
+                            _ => return Err(reject::custom(NoSessionIdHeader)),
+                        };
+
+                        match inner_self.idp.validate(&inner_self.pool, session_id).await {
+                            Err(err) => {
+                                error!(err:err = err; "Failed to validate session");
+                                Err(reject::custom(InternalError))
+                            }
+                            Ok(principal) => principal.ok_or(reject::custom(InvalidSessionId)),
+                        }
                     }
-                    Ok(mut tx) => {
-                        if inner_self
-                            .idp
-                            .is_valid(&mut tx, session_id.to_owned())
-                            .await
-                        {
-                            Ok(session_id.to_owned())
-                        } else {
-                            Err(reject::custom(InvalidSessionId))
+                },
+            )
+    }
+
+    /// Refresh token from the cookie, protected by the CSRF token of the (possibly expired) session cookie.
+    /// Always `None` when cookie sessions are disabled
+    pub fn refresh_cookie(
+        self: Arc<Self>,
+    ) -> impl Filter<Extract = (Option<String>,), Error = Rejection> + Clone {
+        warp::cookie::optional(REFRESH_COOKIE)
+            .and(warp::cookie::optional(SESSION_COOKIE))
+            .and(warp::header::optional(CSRF_HEADER))
+            .and(warp::method())
+            .and_then(
+                move |refresh_cookie: Option<String>,
+                      session_cookie: Option<String>,
+                      csrf_header: Option<String>,
+                      method: Method| {
+                    let cookies = self.cookies.clone();
+                    async move {
+                        let Some(cookies) = cookies else {
+                            return Ok(None);
+                        };
+                        match refresh_cookie.filter(|cookie| !cookie.is_empty()) {
+                            Some(_)
+                                if !cookies.csrf_passed(
+                                    &method,
+                                    session_cookie.as_deref(),
+                                    csrf_header.as_deref(),
+                                ) =>
+                            {
+                                Err(reject::custom(CsrfTokenMismatch))
+                            }
+                            refresh_token => Ok(refresh_token),
                         }
                     }
-                }
-            }
-        })
+                },
+            )
     }
 }
 
 #[derive(Error, Serialize, Debug)]
 pub enum AuthenticationError {
-    #[error("Session id is missing. Expected header - Authorization: session-id [session_id]")]
+    #[error("Session id is missing. Expected header - Authorization: session-id [session_id] or Authorization: Bearer [session_id]")]
     NoSessionIdHeader,
     #[error("Internal Error")]
     InternalError,
     #[error("Invalid session id")]
     InvalidSessionId,
+    #[error("CSRF token is missing or doesn't match. Expected header - X-CSRF-Token: [csrf_token cookie]")]
+    CsrfTokenMismatch,
+    #[error("Not enough permissions")]
+    MissingPermission,
 }
 
 impl Reject for AuthenticationError {}
 
+/// Authenticated caller extracted by [AuthenticationFilter::with_session]
+#[derive(Clone, Debug)]
+pub struct Principal {
+    pub user_id: Uuid,
+    /// Hash of the session id, the raw one is known only to the client
+    pub session_id: String,
+    /// Resolved when the session was issued or loaded into the cache, role changes revoke sessions of the user
+    pub permissions: Vec<String>,
+}
+
+impl Principal {
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|granted| granted == permission)
+    }
+}
+
+/// Request metadata of the client performing authentication
+pub struct ClientInfo {
+    pub address: Option<IpAddr>,
+    pub user_agent: Option<String>,
+}
+
 pub struct Session {
+    /// SHA-256 of the id handed to the client, so leaked rows can't be used as credentials
     pub session_id: String,
+    pub user_id: Uuid,
+    /// Refresh token family the session was issued in, absent for sessions created before refresh tokens
+    pub family_id: Option<Uuid>,
+    pub created: DateTime<Utc>,
+    pub expires: DateTime<Utc>,
+    /// Updated at most once per `session_last_seen_interval_seconds`, not tracked for JWTs
+    pub last_seen: DateTime<Utc>,
+    pub user_agent: Option<String>,
+    pub client_ip: Option<String>,
+}
+
+/// Authentication data of the user as handed out in a personal data export,
+/// secrets like the password hash or TOTP secret are never included
+pub struct AccountExport {
+    pub login: String,
+    pub email: Option<String>,
+    pub state: AccountState,
+    pub two_factor_enabled: bool,
+    pub permissions: Vec<String>,
+    pub sessions: Vec<Session>,
+}
+
+/// Credential handed to the client: session id itself or a signed JWT with `jti` = session id,
+/// accompanied by a single-use refresh token
+pub struct IssuedToken {
+    pub token: String,
     pub expires: DateTime<Utc>,
+    pub refresh_token: String,
+    pub refresh_expires: DateTime<Utc>,
+}
+
+/// Result of the password check, accounts with TOTP enabled have to complete the challenge first
+pub enum AuthenticationOutcome {
+    Authenticated(IssuedToken),
+    ChallengeRequired {
+        challenge: String,
+        expires: DateTime<Utc>,
+    },
+}
+
+/// Proof of the second factor completing a login challenge
+pub enum SecondFactor {
+    Code(String),
+    RecoveryCode(String),
+}
+
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub uri: String,
 }
 
 impl Session {
-    fn to_cached(&self) -> CachedSession {
+    fn to_cached(&self, permissions: Vec<String>) -> CachedSession {
         CachedSession {
+            user_id: Some(self.user_id),
+            created: Some(self.created),
             invalid: false,
-            expires: Some(self.expires.clone()),
+            expires: Some(self.expires),
+            last_seen: Some(self.last_seen),
+            permissions,
         }
     }
 }
@@ -110,19 +315,145 @@ where
     Self: Send + Sync,
     Pool: DatabasePool
 {
-    async fn is_valid(&self, tx: &mut Pool::Tx, session_id: String) -> bool;
+    /// Resolves session into its principal.
+    /// The cache is consulted first, a transaction is started only on cache miss
+    /// or when the session activity has to be recorded
+    async fn validate(
+        &self,
+        pool: &Pool,
+        session_id: String,
+    ) -> Result<Option<Principal>, Pool::Err>;
+    /// Failed attempts are recorded in `tx`, so it should be committed on [IDPError::AuthenticationFailed] too
     async fn authenticate(
         &self,
         tx: &mut Pool::Tx,
         credentials: &Credentials,
-    ) -> Result<Session, IDPError<Pool::Err>>;
+        client: &ClientInfo,
+    ) -> Result<AuthenticationOutcome, IDPError<Pool::Err>>;
+    /// Exchanges login challenge and the second factor for a session.
+    /// The challenge is consumed by any attempt, so `tx` should be committed on [IDPError::InvalidTotpCode] too
+    async fn complete_challenge(
+        &self,
+        tx: &mut Pool::Tx,
+        challenge: &str,
+        second_factor: &SecondFactor,
+        client: &ClientInfo,
+    ) -> Result<IssuedToken, IDPError<Pool::Err>>;
+    /// Generates a new TOTP secret, it takes effect only after [IDPContext::confirm_totp]
+    async fn enroll_totp(
+        &self,
+        tx: &mut Pool::Tx,
+        principal: &Principal,
+    ) -> Result<TotpEnrollment, IDPError<Pool::Err>>;
+    /// Enables TOTP once the code from the authenticator matches, returns recovery codes
+    async fn confirm_totp(
+        &self,
+        tx: &mut Pool::Tx,
+        principal: &Principal,
+        code: &str,
+    ) -> Result<Vec<String>, IDPError<Pool::Err>>;
+    /// Account is created pending and the verification token is sent to `email`
+    /// when email verification is required, `email` is optional otherwise
     async fn add_user(
         &self,
         tx: &mut Pool::Tx,
         login: &str,
         password: &str,
+        email: Option<&str>,
         user: &User,
     ) -> Result<(), IDPError<Pool::Err>>;
+    /// Consumes verification token and activates the pending account
+    async fn verify_email(
+        &self,
+        tx: &mut Pool::Tx,
+        token: &str,
+    ) -> Result<(), IDPError<Pool::Err>>;
+    /// Exchanges refresh token for a new session and refresh token of the same family.
+    /// Reuse of an already exchanged token revokes the whole family, so `tx` should be committed
+    /// on [IDPError::RefreshTokenReused] too
+    async fn refresh(
+        &self,
+        tx: &mut Pool::Tx,
+        refresh_token: &str,
+        client: &ClientInfo,
+    ) -> Result<IssuedToken, IDPError<Pool::Err>>;
+    /// Replaces roles of the user and revokes every session of the user, so new permissions apply at once.
+    /// Returns amount of revoked sessions
+    async fn set_roles(
+        &self,
+        tx: &mut Pool::Tx,
+        user_id: Uuid,
+        roles: &[String],
+    ) -> Result<usize, IDPError<Pool::Err>>;
+    /// Not expired sessions of the principal's user
+    async fn sessions(
+        &self,
+        tx: &mut Pool::Tx,
+        principal: &Principal,
+    ) -> Result<Vec<Session>, IDPError<Pool::Err>>;
+    /// Authentication data of the user for a personal data export
+    async fn export(
+        &self,
+        tx: &mut Pool::Tx,
+        user_id: Uuid,
+    ) -> Result<AccountExport, ExportError<Pool::Err>>;
+    /// Revokes session of the principal's user by its hashed id, returns amount of revoked sessions.
+    /// Sessions of other users are reported as [IDPError::SessionNotFound]
+    async fn revoke_own(
+        &self,
+        tx: &mut Pool::Tx,
+        principal: &Principal,
+        session_id: &str,
+    ) -> Result<usize, IDPError<Pool::Err>>;
+    /// Deactivates login of the principal's user and revokes every session of the user.
+    /// Returns amount of revoked sessions
+    async fn deactivate(
+        &self,
+        tx: &mut Pool::Tx,
+        principal: &Principal,
+    ) -> Result<usize, IDPError<Pool::Err>>;
+    /// Revokes a single session, returns amount of revoked sessions
+    async fn revoke(
+        &self,
+        tx: &mut Pool::Tx,
+        session_id: &str,
+    ) -> Result<usize, IDPError<Pool::Err>>;
+    /// Revokes every session of the principal's user, returns amount of revoked sessions
+    async fn revoke_all(
+        &self,
+        tx: &mut Pool::Tx,
+        principal: &Principal,
+    ) -> Result<usize, IDPError<Pool::Err>>;
+    /// Verifies the current password, replaces it and revokes every other session of the user.
+    /// Returns amount of revoked sessions
+    async fn change_password(
+        &self,
+        tx: &mut Pool::Tx,
+        principal: &Principal,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<usize, IDPError<Pool::Err>>;
+    /// Issues single-use reset token and delivers it through the notifier in background.
+    /// Unknown logins are silently ignored, so the response doesn't reveal which logins exist
+    async fn request_password_reset(
+        &self,
+        tx: &mut Pool::Tx,
+        login: &str,
+    ) -> Result<(), IDPError<Pool::Err>>;
+    /// Consumes reset token, replaces password and revokes every session of the user.
+    /// Returns amount of revoked sessions
+    async fn reset_password(
+        &self,
+        tx: &mut Pool::Tx,
+        token: &str,
+        new_password: &str,
+    ) -> Result<usize, IDPError<Pool::Err>>;
+    /// Applies revocation performed by another instance to the local cache
+    fn apply_revocation(&self, session_id: String);
+    /// Drops every cached session and reloads revoked tokens,
+    /// used when remote revocations could have been missed
+    async fn resync(&self, pool: &Pool) -> Result<(), Pool::Err>;
+    fn session_cache_stats(&self) -> SessionCacheStats;
 }
 
 pub struct PgIDPContext<Pool, SessionRepo, AuthRepo>
@@ -132,23 +463,39 @@ where
     AuthRepo: AuthRepository<Pool>,
 {
     session_repo: Arc<SessionRepo>,
-    session_cache: DashMap<String, CachedSession>,
+    session_cache: SessionCache,
     session_lifetime: Duration,
-    invalid_sessions: ConcurrentQueue<String>,
+    /// Present when sliding expiration is enabled, which is allowed only in [SessionMode::Session]
+    sliding: Option<SlidingExpiration>,
+    last_seen_interval: Duration,
+    refresh_token_lifetime: Duration,
     auth_repo: Arc<AuthRepo>,
+    hashing_pool: HashingPool,
+    hashers: Arc<PasswordHashers>,
+    notifier: Arc<dyn Notifier>,
+    password_reset_token_lifetime: Duration,
+    email_verification_required: bool,
+    email_verification_token_lifetime: Duration,
+    /// Prefix of links sent to users, without the trailing slash
+    public_base_url: String,
+    lockout: LockoutPolicy,
+    totp_issuer: String,
+    totp_challenge_lifetime: Duration,
+    totp_recovery_codes: usize,
+    /// Present in [SessionMode::Jwt], tokens are then validated without touching the database
+    jwt: Option<JwtCodec>,
+    revoked_tokens: RevokedTokens,
     pool: PhantomData<Pool>,
 }
 
-#[derive(Clone)]
-struct CachedSession {
-    expires: Option<DateTime<Utc>>,
-    invalid: bool,
-}
-
-impl CachedSession {
-    pub fn valid(&self) -> bool {
-        !self.invalid && self.expires.map(|time| time > Utc::now()).unwrap_or(false)
-    }
+#[derive(Error, Debug)]
+pub enum IDPConfigError {
+    #[error("Invalid password hasher configuration")]
+    PasswordHasher(#[from] PasswordHashError),
+    #[error("Invalid JWT configuration")]
+    Jwt(#[from] JwtConfigError),
+    #[error("Sliding expiration is supported only in session mode, JWT expiry is fixed at signing")]
+    SlidingJwt,
 }
 
 impl<Pool, SessionRepo, AuthRepo> PgIDPContext<Pool, SessionRepo, AuthRepo>
@@ -160,66 +507,399 @@ where
     pub fn new(
         session_repo: Arc<SessionRepo>,
         auth_repo: Arc<AuthRepo>,
+        notifier: Arc<dyn Notifier>,
         auth_config: &AuthConfig,
-    ) -> Self {
-        Self {
+    ) -> Result<Self, IDPConfigError> {
+        if auth_config.session_sliding && matches!(auth_config.session_mode, SessionMode::Jwt) {
+            return Err(IDPConfigError::SlidingJwt);
+        }
+
+        Ok(Self {
             session_repo,
-            session_cache: DashMap::new(),
+            session_cache: SessionCache::new(auth_config),
             session_lifetime: Duration::seconds(auth_config.session_lifetime_seconds as i64),
-            invalid_sessions: ConcurrentQueue::bounded(auth_config.invalid_sessions_cache_limit),
+            sliding: SlidingExpiration::new(auth_config),
+            last_seen_interval: Duration::seconds(auth_config.session_last_seen_interval_seconds as i64),
+            refresh_token_lifetime: Duration::seconds(auth_config.refresh_token_lifetime_seconds as i64),
             auth_repo,
+            hashing_pool: HashingPool::new(auth_config),
+            hashers: Arc::new(PasswordHashers::new(auth_config)?),
+            notifier,
+            password_reset_token_lifetime: Duration::seconds(
+                auth_config.password_reset_token_lifetime_seconds as i64,
+            ),
+            email_verification_required: auth_config.email_verification_required,
+            email_verification_token_lifetime: Duration::seconds(
+                auth_config.email_verification_token_lifetime_seconds as i64,
+            ),
+            public_base_url: auth_config.public_base_url.trim_end_matches('/').to_owned(),
+            lockout: LockoutPolicy::new(auth_config),
+            totp_issuer: auth_config.totp_issuer.clone(),
+            totp_challenge_lifetime: Duration::seconds(auth_config.totp_challenge_lifetime_seconds as i64),
+            totp_recovery_codes: auth_config.totp_recovery_codes,
+            jwt: match auth_config.session_mode {
+                SessionMode::Session => None,
+                SessionMode::Jwt => Some(JwtCodec::new(auth_config)?),
+            },
+            revoked_tokens: RevokedTokens::new(),
             pool: PhantomData,
+        })
+    }
+
+    async fn hash_password(&self, password: &str) -> Result<String, IDPError<Pool::Err>> {
+        let hashers = self.hashers.clone();
+        let password = password.to_owned();
+        self.hashing_pool
+            .run(move || hashers.hash(&password))
+            .await?
+            .map_err(IDPError::CryptoError)
+    }
+
+    async fn verify_password(&self, password: &str, hash: &str) -> Result<bool, IDPError<Pool::Err>> {
+        let hashers = self.hashers.clone();
+        let password = password.to_owned();
+        let hash = hash.to_owned();
+        self.hashing_pool
+            .run(move || hashers.verify(&password, &hash))
+            .await?
+            .map_err(IDPError::CryptoError)
+    }
+
+    /// Replaces hash produced by an outdated algorithm or parameters, password is known only on login
+    async fn rehash_if_outdated(
+        &self,
+        tx: &mut Pool::Tx,
+        credentials: &StoredCredentials,
+        password: &str,
+    ) -> Result<(), IDPError<Pool::Err>> {
+        if !self.hashers.needs_rehash(&credentials.password) {
+            return Ok(());
         }
+
+        match self.hash_password(password).await {
+            Ok(new_hash) => {
+                self.auth_repo
+                    .update_password(tx, credentials.user_id, &new_hash)
+                    .await
+                    .map_err(IDPError::AuthenticationError)?;
+                info!(login = credentials.login; "Upgraded password hash");
+            }
+            Err(err) => warn!(login = credentials.login, err:err = err; "Failed to upgrade password hash"),
+        }
+
+        Ok(())
     }
 
-    fn invalidate_session(
+    async fn ensure_not_locked(
         &self,
-        from_cache: DashMapRef<String, CachedSession>,
-        session_id: &str,
-    ) -> () {
-        drop(from_cache); // To eliminate deadlock
+        tx: &mut Pool::Tx,
+        keys: &[LockoutKey],
+        now: DateTime<Utc>,
+    ) -> Result<(), IDPError<Pool::Err>> {
+        let keys: Vec<String> = keys.iter().map(LockoutKey::as_key).collect();
+        let locked_until = self
+            .auth_repo
+            .locked_until(tx, &keys, now)
+            .await
+            .map_err(IDPError::AuthenticationError)?;
 
-        self.session_cache
-            .alter(session_id, |_, mut cached_session| {
-                cached_session.invalid = true;
-                cached_session
-            });
+        match locked_until {
+            Some(until) => Err(IDPError::TooManyAttempts {
+                retry_after_seconds: (until - now).num_seconds().max(1),
+            }),
+            None => Ok(()),
+        }
+    }
 
-        self.add_session_to_invalidation_queue(session_id);
+    /// Rejects accounts which may not obtain new sessions, neither by login nor by refresh token
+    fn ensure_can_sign_in(&self, credentials: &StoredCredentials) -> Result<(), IDPError<Pool::Err>> {
+        match credentials.state {
+            // Indistinguishable from unknown login, deleted accounts shouldn't be discoverable
+            AccountState::Deleted => Err(IDPError::AuthenticationFailed),
+            AccountState::Disabled => Err(IDPError::AccountDisabled),
+            AccountState::Pending if self.email_verification_required => {
+                info!(login = credentials.login; "Refused sign in to unverified account");
+                Err(IDPError::AccountNotVerified)
+            }
+            _ => Ok(()),
+        }
     }
 
-    fn cache_session(&self, session_id: String, session: CachedSession) {
-        let invalid = session.invalid;
-        self.session_cache.insert(session_id.clone(), session);
+    async fn record_failed_login(
+        &self,
+        tx: &mut Pool::Tx,
+        keys: &[LockoutKey],
+        now: DateTime<Utc>,
+    ) -> Result<(), IDPError<Pool::Err>> {
+        for key in keys {
+            let key_str = key.as_key();
+            let failures = self
+                .auth_repo
+                .record_failure(tx, &key_str, now, self.lockout.window_start(now))
+                .await
+                .map_err(IDPError::AuthenticationError)?;
 
-        if invalid {
-            self.add_session_to_invalidation_queue(&session_id);
+            if let Some(lock_duration) = self.lockout.lock_duration(key, failures) {
+                self.auth_repo
+                    .lock(tx, &key_str, now.add(lock_duration))
+                    .await
+                    .map_err(IDPError::AuthenticationError)?;
+                warn!(
+                    key = key_str,
+                    failures = failures,
+                    lock_seconds = lock_duration.num_seconds();
+                    "Locked login attempts"
+                );
+            }
         }
+
+        Ok(())
     }
 
-    fn add_session_to_invalidation_queue(&self, session_id: &str) {
-        if self.invalid_sessions.is_full() {
-            let _ = self.invalid_sessions.pop()
-                .map_err(|err| error!(err:err = err; "Unexpected session invalidation invalidation error"))
-                .map(|session_id| self.session_cache.remove(&session_id));
+    /// Creates session and refresh token of the `family_id` family
+    async fn issue_session(
+        &self,
+        tx: &mut Pool::Tx,
+        user_id: Uuid,
+        family_id: Uuid,
+        client: &ClientInfo,
+    ) -> Result<IssuedToken, IDPError<Pool::Err>> {
+        let now = Utc::now();
+        let session_id = Uuid::new_v4().to_string();
+        let expires = now.add(self.session_lifetime);
+        let permissions = self
+            .auth_repo
+            .find_permissions(tx, user_id)
+            .await
+            .map_err(IDPError::AuthenticationError)?;
+        let token = match &self.jwt {
+            Some(jwt) => jwt
+                .issue(user_id, &session_id, expires, &permissions)
+                .map_err(IDPError::TokenError)?,
+            None => session_id.clone(),
+        };
+        let session = Session {
+            session_id: tokens::hash_token(&session_id),
+            user_id,
+            family_id: Some(family_id),
+            created: now,
+            expires,
+            last_seen: now,
+            user_agent: client.user_agent.clone(),
+            client_ip: client.address.map(|address| address.to_string()),
+        };
+        self.session_repo
+            .save(tx, &session)
+            .await
+            .map_err(IDPError::AuthenticationError)?;
+
+        let refresh_token = tokens::generate_token();
+        let refresh_expires = now.add(self.refresh_token_lifetime);
+        self.session_repo
+            .save_refresh_token(
+                tx,
+                &RefreshToken {
+                    token_hash: tokens::hash_token(&refresh_token),
+                    family_id,
+                    user_id,
+                    expires: refresh_expires,
+                    used: false,
+                },
+            )
+            .await
+            .map_err(IDPError::AuthenticationError)?;
+
+        if self.jwt.is_none() {
+            self.cache_session(session.session_id.clone(), session.to_cached(permissions));
+        }
+
+        Ok(IssuedToken {
+            token,
+            expires,
+            refresh_token,
+            refresh_expires,
+        })
+    }
+
+    async fn revoke_sessions_of_user(
+        &self,
+        tx: &mut Pool::Tx,
+        user_id: Uuid,
+        keep: Option<&str>,
+    ) -> Result<usize, IDPError<Pool::Err>> {
+        self.session_repo
+            .delete_refresh_tokens_of_user(tx, user_id, keep)
+            .await
+            .map_err(IDPError::RevocationError)?;
+        let deleted = self
+            .session_repo
+            .delete_all_of_user(tx, user_id, keep)
+            .await
+            .map_err(IDPError::RevocationError)?;
+        let revoked = deleted.len();
+        self.publish_revocations(tx, deleted).await?;
+
+        Ok(revoked)
+    }
+
+    /// Notifies other instances and, for JWTs which stay verifiable until expiration,
+    /// persists token ids to the revocation list
+    async fn publish_revocations(
+        &self,
+        tx: &mut Pool::Tx,
+        session_ids: Vec<String>,
+    ) -> Result<(), IDPError<Pool::Err>> {
+        self.session_repo
+            .notify_revoked(tx, &session_ids)
+            .await
+            .map_err(IDPError::RevocationError)?;
+        if self.jwt.is_some() {
+            self.session_repo
+                .save_revoked_tokens(tx, &session_ids, Utc::now().add(self.session_lifetime))
+                .await
+                .map_err(IDPError::RevocationError)?;
+        }
+        for session_id in session_ids {
+            self.revoke_cached_session(session_id);
         }
 
-        let _ = self.invalid_sessions.push(session_id.to_owned());
+        Ok(())
+    }
 
+    fn cache_session(&self, session_id: String, session: CachedSession) {
+        self.session_cache.insert(session_id, session);
+    }
+
+    fn revoke_cached_session(&self, session_id: String) {
         info!(session_id = session_id; "Invalidated session");
+        if self.jwt.is_some() {
+            // Token issued right now expires no later than that
+            self.revoked_tokens
+                .revoke(session_id.clone(), Utc::now().add(self.session_lifetime));
+        }
+        self.cache_session(session_id, CachedSession::invalid());
     }
 
-    fn is_valid_session_in_cache(&self, session_id: &str) -> Option<bool> {
-        self.session_cache.get(session_id).map(|cached_session| {
-            if cached_session.value().valid() {
-                true
-            } else if !cached_session.value().invalid {
-                self.invalidate_session(cached_session, session_id);
-                false
-            } else {
-                false
+    fn principal_from_jwt(&self, jwt: &JwtCodec, token: &str) -> Option<Principal> {
+        jwt.verify(token)
+            .map(|claims| Principal {
+                user_id: claims.sub,
+                session_id: tokens::hash_token(&claims.jti),
+                permissions: claims.permissions,
+            })
+            .filter(|principal| !self.revoked_tokens.is_revoked(&principal.session_id))
+    }
+
+    async fn load_session(&self, pool: &Pool, session_id: &str) -> Result<CachedSession, Pool::Err> {
+        let mut tx = pool.begin_tx().await?;
+        let from_db = self.session_repo.find(&mut tx, session_id).await;
+
+        let session = match from_db {
+            Some(not_expired) if not_expired.expires > Utc::now() => {
+                let permissions = self
+                    .auth_repo
+                    .find_permissions(&mut tx, not_expired.user_id)
+                    .await?;
+                not_expired.to_cached(permissions)
             }
-        })
+            Some(expired) => CachedSession {
+                invalid: true,
+                ..expired.to_cached(vec![])
+            },
+            None => CachedSession::invalid(),
+        };
+        self.cache_session(session_id.to_owned(), session.clone());
+
+        Ok(session)
+    }
+
+    /// Checks the code against the secret in the `confirmed` state and marks its time step as used
+    async fn verify_totp_code(
+        &self,
+        tx: &mut Pool::Tx,
+        user_id: Uuid,
+        code: &str,
+        confirmed: bool,
+    ) -> Result<bool, IDPError<Pool::Err>> {
+        let credential = self
+            .auth_repo
+            .find_totp(tx, user_id)
+            .await
+            .map_err(IDPError::AuthenticationError)?
+            .filter(|credential| credential.confirmed == confirmed);
+        let Some(step) =
+            credential.and_then(|credential| totp::verify(&credential.secret, code, Utc::now()))
+        else {
+            return Ok(false);
+        };
+
+        self.auth_repo
+            .use_totp_step(tx, user_id, step)
+            .await
+            .map_err(IDPError::AuthenticationError)
+    }
+
+    async fn verify_second_factor(
+        &self,
+        tx: &mut Pool::Tx,
+        user_id: Uuid,
+        second_factor: &SecondFactor,
+    ) -> Result<bool, IDPError<Pool::Err>> {
+        match second_factor {
+            SecondFactor::Code(code) => self.verify_totp_code(tx, user_id, code, true).await,
+            SecondFactor::RecoveryCode(code) => self
+                .auth_repo
+                .consume_recovery_code(
+                    tx,
+                    user_id,
+                    &tokens::hash_token(&totp::normalize_recovery_code(code)),
+                )
+                .await
+                .map_err(IDPError::AuthenticationError),
+        }
+    }
+
+    /// Persists activity and extended expiration when the session is due for them,
+    /// otherwise returns it unchanged
+    async fn touch_session(
+        &self,
+        pool: &Pool,
+        session_id: &str,
+        session: CachedSession,
+    ) -> Result<CachedSession, Pool::Err> {
+        let (Some(created), Some(expires), Some(last_seen)) =
+            (session.created, session.expires, session.last_seen)
+        else {
+            return Ok(session);
+        };
+        let now = Utc::now();
+        let extended = self
+            .sliding
+            .as_ref()
+            .and_then(|sliding| sliding.extended_expiry(now, created, expires));
+        if extended.is_none() && now - last_seen < self.last_seen_interval {
+            return Ok(session);
+        }
+
+        let mut tx = pool.begin_tx().await?;
+        let exists = self
+            .session_repo
+            .touch(&mut tx, session_id, now, extended)
+            .await?;
+        tx.commit().await?;
+
+        let session = if exists {
+            CachedSession {
+                expires: extended.or(session.expires),
+                last_seen: Some(now),
+                ..session
+            }
+        } else {
+            CachedSession::invalid()
+        };
+        self.cache_session(session_id.to_owned(), session.clone());
+
+        Ok(session)
     }
 }
 
@@ -231,33 +911,35 @@ where
     SessionRepo: SessionRepository<Pool>,
     AuthRepo: AuthRepository<Pool>,
 {
-    async fn is_valid(&self, tx: &mut Pool::Tx, session_id: String) -> bool {
-        if Uuid::from_str(&session_id).is_err() {
-            false
-        } else if let Some(cached) = self.is_valid_session_in_cache(&session_id) {
-            cached
+    async fn validate(
+        &self,
+        pool: &Pool,
+        session_id: String,
+    ) -> Result<Option<Principal>, Pool::Err> {
+        if let Some(jwt) = &self.jwt {
+            Ok(self.principal_from_jwt(jwt, &session_id))
+        } else if Uuid::from_str(&session_id).is_err() {
+            Ok(None)
         } else {
-            let from_db = self.session_repo.not_expired(tx, &session_id).await;
-
-            let session = match from_db {
-                Some(not_expired) if not_expired > Utc::now() => CachedSession {
-                    expires: Some(not_expired),
-                    invalid: false,
-                },
-                Some(expired) => CachedSession {
-                    expires: Some(expired),
-                    invalid: true,
-                },
-                None => CachedSession {
-                    expires: None,
-                    invalid: true,
-                },
+            let session_id = tokens::hash_token(&session_id);
+            let session = match self.session_cache.get(&session_id) {
+                Some(cached) => cached,
+                None => self.load_session(pool, &session_id).await?,
+            };
+            let session = if session.valid() {
+                self.touch_session(pool, &session_id, session).await?
+            } else {
+                session
             };
 
-            let is_valid = !session.invalid;
-            self.cache_session(session_id, session);
-
-            is_valid
+            Ok(session
+                .user_id
+                .filter(|_| session.valid())
+                .map(|user_id| Principal {
+                    user_id,
+                    session_id,
+                    permissions: session.permissions,
+                }))
         }
     }
 
@@ -265,43 +947,204 @@ where
         &self,
         tx: &mut Pool::Tx,
         credentials: &Credentials,
-    ) -> Result<Session, IDPError<Pool::Err>> {
-        let db_credentials = self
-            .auth_repo
-            .find(tx, &credentials.login)
-            .await
-            .ok_or(IDPError::AuthenticationFailed)?;
+        client: &ClientInfo,
+    ) -> Result<AuthenticationOutcome, IDPError<Pool::Err>> {
+        let now = Utc::now();
+        let lockout_keys = self.lockout.keys(&credentials.login, client.address);
+        self.ensure_not_locked(tx, &lockout_keys, now).await?;
 
-        if bcrypt::verify(&credentials.password, &db_credentials.password)
-            .map_err(|err| IDPError::CryptoError(err))?
+        let Some(db_credentials) = self.auth_repo.find(tx, &credentials.login).await else {
+            // Same amount of hashing work as for existing login, so timing doesn't reveal which logins exist.
+            // Accounts with an outdated hash are verified at its cost until the next successful login
+            // upgrades it, bench/login-timing.sh measures that residual difference
+            let _ = self
+                .verify_password(&credentials.password, self.hashers.dummy_hash())
+                .await?;
+            self.record_failed_login(tx, &lockout_keys, now).await?;
+            return Err(IDPError::AuthenticationFailed);
+        };
+
+        if self
+            .verify_password(&credentials.password, &db_credentials.password)
+            .await?
         {
-            let session = Session {
-                session_id: Uuid::new_v4().to_string(),
-                expires: Utc::now().add(self.session_lifetime),
-            };
+            // Checked before touching lockout counters and the hash, so a refused account leaves no trace
+            if let Err(err) = self.ensure_can_sign_in(&db_credentials) {
+                if db_credentials.state == AccountState::Deleted {
+                    self.record_failed_login(tx, &lockout_keys, now).await?;
+                }
+                return Err(err);
+            }
 
-            self.session_repo
-                .save(tx, &session)
+            self.auth_repo
+                .clear_failures(tx, &LockoutKey::Login(credentials.login.clone()).as_key())
+                .await
+                .map_err(IDPError::AuthenticationError)?;
+            self.rehash_if_outdated(tx, &db_credentials, &credentials.password)
+                .await?;
+
+            let totp_enabled = self
+                .auth_repo
+                .find_totp(tx, db_credentials.user_id)
                 .await
-                .map_err(|err| IDPError::AuthenticationError(err))?;
-            self.cache_session(session.session_id.clone(), session.to_cached());
+                .map_err(IDPError::AuthenticationError)?
+                .is_some_and(|credential| credential.confirmed);
+            if totp_enabled {
+                let challenge = tokens::generate_token();
+                let expires = Utc::now().add(self.totp_challenge_lifetime);
+                self.auth_repo
+                    .save_login_challenge(
+                        tx,
+                        &tokens::hash_token(&challenge),
+                        db_credentials.user_id,
+                        expires,
+                    )
+                    .await
+                    .map_err(IDPError::AuthenticationError)?;
+                info!(login = credentials.login; "Verified password, awaiting second factor");
+
+                return Ok(AuthenticationOutcome::ChallengeRequired { challenge, expires });
+            }
+
+            let issued = self
+                .issue_session(tx, db_credentials.user_id, Uuid::new_v4(), client)
+                .await?;
             info!(login = credentials.login; "Authenticated user and generated new Session");
 
-            Ok(session)
+            Ok(AuthenticationOutcome::Authenticated(issued))
         } else {
+            self.record_failed_login(tx, &lockout_keys, now).await?;
             Err(IDPError::AuthenticationFailed)
         }
     }
 
+    async fn complete_challenge(
+        &self,
+        tx: &mut Pool::Tx,
+        challenge: &str,
+        second_factor: &SecondFactor,
+        client: &ClientInfo,
+    ) -> Result<IssuedToken, IDPError<Pool::Err>> {
+        let challenge = self
+            .auth_repo
+            .consume_login_challenge(tx, &tokens::hash_token(challenge))
+            .await
+            .map_err(IDPError::AuthenticationError)?
+            .filter(|challenge| challenge.expires > Utc::now())
+            .ok_or(IDPError::InvalidLoginChallenge)?;
+
+        if !self
+            .verify_second_factor(tx, challenge.user_id, second_factor)
+            .await?
+        {
+            warn!(user_id:display = challenge.user_id; "Rejected second factor");
+            return Err(IDPError::InvalidTotpCode);
+        }
+
+        let issued = self
+            .issue_session(tx, challenge.user_id, Uuid::new_v4(), client)
+            .await?;
+        info!(user_id:display = challenge.user_id; "Authenticated user with second factor");
+
+        Ok(issued)
+    }
+
+    async fn enroll_totp(
+        &self,
+        tx: &mut Pool::Tx,
+        principal: &Principal,
+    ) -> Result<TotpEnrollment, IDPError<Pool::Err>> {
+        let enabled = self
+            .auth_repo
+            .find_totp(tx, principal.user_id)
+            .await
+            .map_err(IDPError::AuthenticationError)?
+            .is_some_and(|credential| credential.confirmed);
+        if enabled {
+            return Err(IDPError::TotpAlreadyEnabled);
+        }
+
+        let db_credentials = self
+            .auth_repo
+            .find_by_user(tx, principal.user_id)
+            .await
+            .ok_or(IDPError::AuthenticationFailed)?;
+        let secret = totp::generate_secret();
+        self.auth_repo
+            .save_totp(tx, principal.user_id, &secret)
+            .await
+            .map_err(IDPError::AuthenticationError)?;
+        info!(login = db_credentials.login; "Started TOTP enrollment");
+
+        Ok(TotpEnrollment {
+            uri: totp::otpauth_uri(&self.totp_issuer, &db_credentials.login, &secret),
+            secret,
+        })
+    }
+
+    async fn confirm_totp(
+        &self,
+        tx: &mut Pool::Tx,
+        principal: &Principal,
+        code: &str,
+    ) -> Result<Vec<String>, IDPError<Pool::Err>> {
+        match self
+            .auth_repo
+            .find_totp(tx, principal.user_id)
+            .await
+            .map_err(IDPError::AuthenticationError)?
+        {
+            None => return Err(IDPError::TotpNotEnrolled),
+            Some(credential) if credential.confirmed => return Err(IDPError::TotpAlreadyEnabled),
+            Some(_) => {}
+        }
+
+        if !self
+            .verify_totp_code(tx, principal.user_id, code, false)
+            .await?
+        {
+            return Err(IDPError::InvalidTotpCode);
+        }
+
+        self.auth_repo
+            .confirm_totp(tx, principal.user_id)
+            .await
+            .map_err(IDPError::AuthenticationError)?;
+        let recovery_codes = totp::generate_recovery_codes(self.totp_recovery_codes);
+        let code_hashes: Vec<String> = recovery_codes
+            .iter()
+            .map(|code| tokens::hash_token(&totp::normalize_recovery_code(code)))
+            .collect();
+        self.auth_repo
+            .save_recovery_codes(tx, principal.user_id, &code_hashes)
+            .await
+            .map_err(IDPError::AuthenticationError)?;
+        info!(user_id:display = principal.user_id; "Enabled TOTP");
+
+        Ok(recovery_codes)
+    }
+
     async fn add_user(
         &self,
         tx: &mut Pool::Tx,
         login: &str,
         password: &str,
+        email: Option<&str>,
         user: &User,
     ) -> Result<(), IDPError<Pool::Err>> {
-        let encrypted_password = bcrypt::hash(password, bcrypt::DEFAULT_COST)
-            .map_err(|err| IDPError::CryptoError(err))?;
+        let email = email.map(str::trim);
+        if email.is_some_and(|email| email.parse::<lettre::Address>().is_err()) {
+            return Err(IDPError::InvalidEmail);
+        }
+        if email.is_none() && self.email_verification_required {
+            return Err(IDPError::EmailRequired);
+        }
+        let encrypted_password = self.hash_password(password).await?;
+        let state = if self.email_verification_required {
+            AccountState::Pending
+        } else {
+            AccountState::Active
+        };
 
         self.auth_repo
             .save(
@@ -310,6 +1153,8 @@ where
                     login: login.to_owned(),
                     password: encrypted_password,
                 },
+                email,
+                state,
                 user,
             )
             .await
@@ -318,8 +1163,396 @@ where
                     IDPError::UsernameTaken
                 }
                 _ => IDPError::RegistrationError(err),
-            })
-            .unit()
+            })?;
+
+        if let (AccountState::Pending, Some(email)) = (state, email) {
+            let token = tokens::generate_token();
+            let expires = Utc::now().add(self.email_verification_token_lifetime);
+            self.auth_repo
+                .save_verification_token(tx, &tokens::hash_token(&token), user.id, expires)
+                .await
+                .map_err(IDPError::RegistrationError)?;
+
+            let recipient = Recipient {
+                user_id: user.id,
+                email: Some(email.to_owned()),
+            };
+            let notification = Notification::EmailVerification {
+                login: login.to_owned(),
+                link: format!("{}/user/verify/{token}", self.public_base_url),
+                token,
+                expires,
+            };
+            // Without the token the account could never be activated, so registration is rolled back instead
+            self.notifier
+                .notify(&recipient, &notification)
+                .await
+                .map_err(|err| {
+                    error!(login = login, err:err = err; "Failed to deliver email verification token");
+                    IDPError::VerificationDeliveryFailed
+                })?;
+        }
+
+        Ok(())
+    }
+
+    async fn verify_email(
+        &self,
+        tx: &mut Pool::Tx,
+        token: &str,
+    ) -> Result<(), IDPError<Pool::Err>> {
+        let verification_token = self
+            .auth_repo
+            .consume_verification_token(tx, &tokens::hash_token(token))
+            .await
+            .map_err(IDPError::RegistrationError)?
+            .filter(|verification_token| verification_token.expires > Utc::now())
+            .ok_or(IDPError::InvalidVerificationToken)?;
+
+        if !self
+            .auth_repo
+            .activate(tx, verification_token.user_id)
+            .await
+            .map_err(IDPError::RegistrationError)?
+        {
+            warn!(user_id:display = verification_token.user_id; "Verified email of account which isn't pending");
+            return Err(IDPError::InvalidVerificationToken);
+        }
+        info!(user_id:display = verification_token.user_id; "Verified email");
+
+        Ok(())
+    }
+
+    async fn refresh(
+        &self,
+        tx: &mut Pool::Tx,
+        refresh_token: &str,
+        client: &ClientInfo,
+    ) -> Result<IssuedToken, IDPError<Pool::Err>> {
+        let token_hash = tokens::hash_token(refresh_token);
+        let stored = self
+            .session_repo
+            .find_refresh_token(tx, &token_hash)
+            .await
+            .map_err(IDPError::AuthenticationError)?
+            .ok_or(IDPError::InvalidRefreshToken)?;
+
+        if stored.used {
+            // Either the legitimate client or an attacker holds a stolen token, nobody in the family can be trusted
+            let deleted = self
+                .session_repo
+                .delete_family(tx, stored.family_id)
+                .await
+                .map_err(IDPError::RevocationError)?;
+            warn!(
+                user_id:display = stored.user_id,
+                family_id:display = stored.family_id,
+                revoked_sessions = deleted.len();
+                "Refresh token reuse detected, revoked token family"
+            );
+            self.publish_revocations(tx, deleted).await?;
+            return Err(IDPError::RefreshTokenReused);
+        }
+        if stored.expires <= Utc::now() {
+            return Err(IDPError::InvalidRefreshToken);
+        }
+
+        let refused = match self.auth_repo.find_by_user(tx, stored.user_id).await {
+            Some(db_credentials) => self.ensure_can_sign_in(&db_credentials).err(),
+            None => Some(IDPError::InvalidRefreshToken),
+        };
+        if let Some(err) = refused {
+            // Account lost the right to sign in after the family was issued, the family is no longer of use
+            let deleted = self
+                .session_repo
+                .delete_family(tx, stored.family_id)
+                .await
+                .map_err(IDPError::RevocationError)?;
+            warn!(
+                user_id:display = stored.user_id,
+                family_id:display = stored.family_id,
+                revoked_sessions = deleted.len();
+                "Refused refresh for inactive account, revoked token family"
+            );
+            self.publish_revocations(tx, deleted).await?;
+            return Err(match err {
+                IDPError::AuthenticationFailed => IDPError::InvalidRefreshToken,
+                err => err,
+            });
+        }
+
+        self.session_repo
+            .mark_refresh_token_used(tx, &token_hash)
+            .await
+            .map_err(IDPError::AuthenticationError)?;
+        let issued = self
+            .issue_session(tx, stored.user_id, stored.family_id, client)
+            .await?;
+        info!(user_id:display = stored.user_id; "Rotated refresh token");
+
+        Ok(issued)
+    }
+
+    async fn set_roles(
+        &self,
+        tx: &mut Pool::Tx,
+        user_id: Uuid,
+        roles: &[String],
+    ) -> Result<usize, IDPError<Pool::Err>> {
+        self.auth_repo
+            .find_by_user(tx, user_id)
+            .await
+            .ok_or(IDPError::UnknownUser)?;
+
+        let mut roles = roles.to_vec();
+        roles.sort();
+        roles.dedup();
+        self.auth_repo
+            .set_roles(tx, user_id, &roles)
+            .await
+            .map_err(|err| match err {
+                error if error.is_foreign_key_violation() => IDPError::UnknownRole,
+                _ => IDPError::RoleChangeError(err),
+            })?;
+        let revoked = self.revoke_sessions_of_user(tx, user_id, None).await?;
+        info!(user_id:display = user_id, roles:debug = roles; "Changed roles");
+
+        Ok(revoked)
+    }
+
+    async fn sessions(
+        &self,
+        tx: &mut Pool::Tx,
+        principal: &Principal,
+    ) -> Result<Vec<Session>, IDPError<Pool::Err>> {
+        self.session_repo
+            .find_all_of_user(tx, principal.user_id, Utc::now())
+            .await
+            .map_err(IDPError::SessionsError)
+    }
+
+    async fn export(
+        &self,
+        tx: &mut Pool::Tx,
+        user_id: Uuid,
+    ) -> Result<AccountExport, ExportError<Pool::Err>> {
+        let db_credentials = self
+            .auth_repo
+            .find_by_user(tx, user_id)
+            .await
+            .ok_or(ExportError::UserNotFound)?;
+        let two_factor_enabled = self
+            .auth_repo
+            .find_totp(tx, user_id)
+            .await
+            .map_err(ExportError::DataError)?
+            .is_some_and(|credential| credential.confirmed);
+        let permissions = self
+            .auth_repo
+            .find_permissions(tx, user_id)
+            .await
+            .map_err(ExportError::DataError)?;
+        let sessions = self
+            .session_repo
+            .find_all_of_user(tx, user_id, Utc::now())
+            .await
+            .map_err(ExportError::DataError)?;
+
+        Ok(AccountExport {
+            login: db_credentials.login,
+            email: db_credentials.email,
+            state: db_credentials.state,
+            two_factor_enabled,
+            permissions,
+            sessions,
+        })
+    }
+
+    async fn revoke_own(
+        &self,
+        tx: &mut Pool::Tx,
+        principal: &Principal,
+        session_id: &str,
+    ) -> Result<usize, IDPError<Pool::Err>> {
+        self.session_repo
+            .find(tx, session_id)
+            .await
+            .filter(|session| session.user_id == principal.user_id)
+            .ok_or(IDPError::SessionNotFound)?;
+
+        self.revoke(tx, session_id).await
+    }
+
+    async fn deactivate(
+        &self,
+        tx: &mut Pool::Tx,
+        principal: &Principal,
+    ) -> Result<usize, IDPError<Pool::Err>> {
+        self.auth_repo
+            .set_state(tx, principal.user_id, AccountState::Deleted)
+            .await
+            .map_err(IDPError::AccountDeletionError)?;
+        let revoked = self
+            .revoke_sessions_of_user(tx, principal.user_id, None)
+            .await?;
+        info!(user_id:display = principal.user_id, revoked_sessions = revoked; "Deactivated account");
+
+        Ok(revoked)
+    }
+
+    async fn revoke(
+        &self,
+        tx: &mut Pool::Tx,
+        session_id: &str,
+    ) -> Result<usize, IDPError<Pool::Err>> {
+        self.session_repo
+            .delete_refresh_tokens_of_session(tx, session_id)
+            .await
+            .map_err(IDPError::RevocationError)?;
+        let deleted = self
+            .session_repo
+            .delete(tx, session_id)
+            .await
+            .map_err(IDPError::RevocationError)?;
+        self.publish_revocations(tx, vec![session_id.to_owned()])
+            .await?;
+
+        Ok(if deleted { 1 } else { 0 })
+    }
+
+    async fn revoke_all(
+        &self,
+        tx: &mut Pool::Tx,
+        principal: &Principal,
+    ) -> Result<usize, IDPError<Pool::Err>> {
+        self.revoke_sessions_of_user(tx, principal.user_id, None)
+            .await
+    }
+
+    async fn change_password(
+        &self,
+        tx: &mut Pool::Tx,
+        principal: &Principal,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<usize, IDPError<Pool::Err>> {
+        let db_credentials = self
+            .auth_repo
+            .find_by_user(tx, principal.user_id)
+            .await
+            .ok_or(IDPError::AuthenticationFailed)?;
+        if !self
+            .verify_password(old_password, &db_credentials.password)
+            .await?
+        {
+            return Err(IDPError::AuthenticationFailed);
+        }
+
+        let encrypted_password = self.hash_password(new_password).await?;
+        self.auth_repo
+            .update_password(tx, principal.user_id, &encrypted_password)
+            .await
+            .map_err(IDPError::PasswordChangeError)?;
+        let revoked = self
+            .revoke_sessions_of_user(tx, principal.user_id, Some(&principal.session_id))
+            .await?;
+        info!(login = db_credentials.login; "Changed password");
+
+        Ok(revoked)
+    }
+
+    async fn request_password_reset(
+        &self,
+        tx: &mut Pool::Tx,
+        login: &str,
+    ) -> Result<(), IDPError<Pool::Err>> {
+        let Some(db_credentials) = self
+            .auth_repo
+            .find(tx, login)
+            .await
+            .filter(|credentials| credentials.state != AccountState::Deleted)
+        else {
+            info!(login = login; "Requested password reset for unknown login");
+            return Ok(());
+        };
+
+        let token = tokens::generate_token();
+        let expires = Utc::now().add(self.password_reset_token_lifetime);
+        self.auth_repo
+            .save_reset_token(tx, &tokens::hash_token(&token), db_credentials.user_id, expires)
+            .await
+            .map_err(IDPError::PasswordChangeError)?;
+
+        let recipient = Recipient {
+            user_id: db_credentials.user_id,
+            email: db_credentials.email,
+        };
+        let notification = Notification::PasswordReset {
+            login: db_credentials.login,
+            token,
+            expires,
+        };
+        // Delivered in background, waiting for the mail server would reveal which logins exist
+        let notifier = self.notifier.clone();
+        let login = login.to_owned();
+        tokio::spawn(async move {
+            if let Err(err) = notifier.notify(&recipient, &notification).await {
+                error!(login = login, err:err = err; "Failed to deliver password reset token");
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn reset_password(
+        &self,
+        tx: &mut Pool::Tx,
+        token: &str,
+        new_password: &str,
+    ) -> Result<usize, IDPError<Pool::Err>> {
+        let reset_token = self
+            .auth_repo
+            .consume_reset_token(tx, &tokens::hash_token(token))
+            .await
+            .map_err(IDPError::PasswordChangeError)?
+            .filter(|reset_token| reset_token.expires > Utc::now())
+            .ok_or(IDPError::InvalidResetToken)?;
+
+        let encrypted_password = self.hash_password(new_password).await?;
+        self.auth_repo
+            .update_password(tx, reset_token.user_id, &encrypted_password)
+            .await
+            .map_err(IDPError::PasswordChangeError)?;
+        let revoked = self
+            .revoke_sessions_of_user(tx, reset_token.user_id, None)
+            .await?;
+        info!(user_id:display = reset_token.user_id; "Reset password");
+
+        Ok(revoked)
+    }
+
+    fn apply_revocation(&self, session_id: String) {
+        self.revoke_cached_session(session_id);
+    }
+
+    async fn resync(&self, pool: &Pool) -> Result<(), Pool::Err> {
+        self.session_cache.invalidate_all();
+        if self.jwt.is_some() {
+            let mut tx = pool.begin_tx().await?;
+            let revoked = self
+                .session_repo
+                .find_revoked_tokens(&mut tx, Utc::now())
+                .await?;
+            for (jti, expires) in revoked {
+                self.revoked_tokens.revoke(jti, expires);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn session_cache_stats(&self) -> SessionCacheStats {
+        self.session_cache.stats()
     }
 }
 
@@ -327,14 +1560,73 @@ where
 pub enum IDPError<PoolErr: Send + StdError + Sync + 'static> {
     #[error("Incorrect username or password")]
     AuthenticationFailed,
-    #[error("Requested username is occupied")]
+    #[error("Requested username or email is occupied")]
     UsernameTaken,
+    #[error("Invalid email address")]
+    InvalidEmail,
+    #[error("Email address is required to verify the account")]
+    EmailRequired,
+    #[error("Email verification token is invalid or expired")]
+    InvalidVerificationToken,
+    #[error("Failed to deliver email verification token, try again later")]
+    VerificationDeliveryFailed,
+    #[error("Email address isn't verified yet")]
+    AccountNotVerified,
+    #[error("Account is disabled")]
+    AccountDisabled,
+    #[error("Account deletion error")]
+    AccountDeletionError(#[serde(skip)] PoolErr),
     #[error("Authentication error")]
     AuthenticationError(#[serde(skip)] PoolErr),
     #[error("Registration error")]
     RegistrationError(#[serde(skip)] PoolErr),
+    #[error("Session revocation error")]
+    RevocationError(#[serde(skip)] PoolErr),
+    #[error("Failed to load sessions")]
+    SessionsError(#[serde(skip)] PoolErr),
+    #[error("Session not found")]
+    SessionNotFound,
+    #[error("Password change error")]
+    PasswordChangeError(#[serde(skip)] PoolErr),
+    #[error("Password reset token is invalid or expired")]
+    InvalidResetToken,
+    #[error("Refresh token is invalid or expired")]
+    InvalidRefreshToken,
+    #[error("Refresh token was already used, every session issued with it is revoked")]
+    RefreshTokenReused,
+    #[error("Login challenge is invalid or expired, log in again")]
+    InvalidLoginChallenge,
+    #[error("Invalid authentication code")]
+    InvalidTotpCode,
+    #[error("Two-factor authentication is already enabled")]
+    TotpAlreadyEnabled,
+    #[error("Two-factor authentication enrollment wasn't started")]
+    TotpNotEnrolled,
+    #[error("User not found")]
+    UnknownUser,
+    #[error("Unknown role")]
+    UnknownRole,
+    #[error("Role change error")]
+    RoleChangeError(#[serde(skip)] PoolErr),
+    #[error("Failed to issue access token")]
+    TokenError(#[serde(skip)] jsonwebtoken::errors::Error),
+    #[error("Too many failed login attempts, retry in {retry_after_seconds} seconds")]
+    TooManyAttempts { retry_after_seconds: i64 },
     #[error("Cryptographic error")]
-    CryptoError(#[serde(skip)] BcryptError),
+    CryptoError(#[serde(skip)] PasswordHashError),
+    #[error("Too many concurrent authentication requests, try again later")]
+    Overloaded,
+    #[error("Password hashing failed")]
+    HashingFailed,
+}
+
+impl<PoolErr: Send + StdError + Sync + 'static> From<HashingPoolError> for IDPError<PoolErr> {
+    fn from(value: HashingPoolError) -> Self {
+        match value {
+            HashingPoolError::Saturated => IDPError::Overloaded,
+            HashingPoolError::Interrupted => IDPError::HashingFailed,
+        }
+    }
 }
 
 impl<T: Debug + Send + StdError + Sync + 'static> Reject for IDPError<T> {}
@@ -0,0 +1,185 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use thiserror::Error;
+use warp::http::Method;
+
+use crate::config::{AuthConfig, CookieSameSite};
+
+pub const SESSION_COOKIE: &str = "session_id";
+/// Sent only to [REFRESH_PATH], so the long-living token doesn't travel with every request
+pub const REFRESH_COOKIE: &str = "refresh_token";
+pub const REFRESH_PATH: &str = "/token/refresh";
+/// Readable by scripts, has to be echoed in [CSRF_HEADER] on state-changing requests
+pub const CSRF_COOKIE: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+#[derive(Error, Debug)]
+pub enum CookieConfigError {
+    #[error("CSRF secret is required for cookie sessions")]
+    MissingSecret,
+}
+
+/// Builds `Set-Cookie` values for browser clients.
+///
+/// Session token is `HttpOnly`, CSRF protection relies on the double-submit pattern:
+/// a cross-site page can make the browser send cookies, but can't read the CSRF cookie to put it into the header.
+/// CSRF token is an HMAC of the session token, so an attacker able to plant cookies still can't forge a matching pair
+#[derive(Clone)]
+pub(crate) struct SessionCookies {
+    secure: bool,
+    same_site: CookieSameSite,
+    csrf_secret: Arc<[u8]>,
+}
+
+impl SessionCookies {
+    /// `None` when cookie sessions are disabled
+    pub fn new(auth_config: &AuthConfig) -> Result<Option<Self>, CookieConfigError> {
+        if !auth_config.cookie_sessions {
+            return Ok(None);
+        }
+
+        let secret = auth_config
+            .cookie_csrf_secret
+            .as_ref()
+            .filter(|secret| !secret.is_empty())
+            .ok_or(CookieConfigError::MissingSecret)?;
+        Ok(Some(Self {
+            secure: auth_config.cookie_secure,
+            same_site: auth_config.cookie_same_site,
+            csrf_secret: secret.as_bytes().into(),
+        }))
+    }
+
+    /// Session cookie with `token` and the matching CSRF cookie, and refresh cookie with `refresh_token`.
+    /// All of them live until `refresh_expires`: the session cookie is still needed to check CSRF on refresh
+    pub fn issue(
+        &self,
+        token: &str,
+        expires: DateTime<Utc>,
+        refresh_token: &str,
+        refresh_expires: DateTime<Utc>,
+    ) -> Vec<String> {
+        let now = Utc::now();
+        let max_age = (expires.max(refresh_expires) - now).num_seconds().max(0);
+        vec![
+            self.cookie(SESSION_COOKIE, token, "/", max_age, true),
+            self.cookie(CSRF_COOKIE, &self.csrf_token(token), "/", max_age, false),
+            self.cookie(REFRESH_COOKIE, refresh_token, REFRESH_PATH, max_age, true),
+        ]
+    }
+
+    pub fn clear(&self) -> Vec<String> {
+        vec![
+            self.cookie(SESSION_COOKIE, "", "/", 0, true),
+            self.cookie(CSRF_COOKIE, "", "/", 0, false),
+            self.cookie(REFRESH_COOKIE, "", REFRESH_PATH, 0, true),
+        ]
+    }
+
+    fn cookie(&self, name: &str, value: &str, path: &str, max_age: i64, http_only: bool) -> String {
+        let mut cookie = format!(
+            "{name}={value}; Path={path}; Max-Age={max_age}; SameSite={}",
+            self.same_site.as_attribute()
+        );
+        if http_only {
+            cookie.push_str("; HttpOnly");
+        }
+        if self.secure {
+            cookie.push_str("; Secure");
+        }
+        cookie
+    }
+
+    /// Safe methods pass, otherwise the header has to carry the CSRF token of the session cookie
+    pub fn csrf_passed(&self, method: &Method, session: Option<&str>, header: Option<&str>) -> bool {
+        if method.is_safe() {
+            return true;
+        }
+
+        match (session, header) {
+            (Some(session), Some(header)) => {
+                !session.is_empty() && constant_time_eq(&self.csrf_token(session), header)
+            }
+            _ => false,
+        }
+    }
+
+    fn csrf_token(&self, session: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.csrf_secret)
+            .expect("HMAC accepts keys of any length");
+        mac.update(session.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+}
+
+/// Comparison time doesn't depend on the position of the first mismatch
+fn constant_time_eq(left: &str, right: &str) -> bool {
+    left.len() == right.len()
+        && left
+            .bytes()
+            .zip(right.bytes())
+            .fold(0u8, |diff, (left, right)| diff | (left ^ right))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SESSION: &str = "4f1c2a9e-6b3d-4c8a-9e2f-1a7b5c3d9e0f";
+
+    fn cookies(secret: &str) -> SessionCookies {
+        SessionCookies {
+            secure: true,
+            same_site: CookieSameSite::Strict,
+            csrf_secret: secret.as_bytes().into(),
+        }
+    }
+
+    #[test]
+    fn accepts_token_of_the_session() {
+        let cookies = cookies("secret");
+        let token = cookies.csrf_token(SESSION);
+        assert!(cookies.csrf_passed(&Method::POST, Some(SESSION), Some(&token)));
+    }
+
+    #[test]
+    fn rejects_token_of_another_session() {
+        let cookies = cookies("secret");
+        let token = cookies.csrf_token("another-session");
+        assert!(!cookies.csrf_passed(&Method::POST, Some(SESSION), Some(&token)));
+    }
+
+    #[test]
+    fn rejects_token_signed_with_another_secret() {
+        let token = cookies("another-secret").csrf_token(SESSION);
+        assert!(!cookies("secret").csrf_passed(&Method::DELETE, Some(SESSION), Some(&token)));
+    }
+
+    #[test]
+    fn rejects_missing_values() {
+        let cookies = cookies("secret");
+        let token = cookies.csrf_token(SESSION);
+        assert!(!cookies.csrf_passed(&Method::POST, Some(SESSION), None));
+        assert!(!cookies.csrf_passed(&Method::POST, None, Some(&token)));
+        assert!(!cookies.csrf_passed(&Method::POST, Some(""), Some(&cookies.csrf_token(""))));
+    }
+
+    #[test]
+    fn passes_safe_methods_without_token() {
+        let cookies = cookies("secret");
+        assert!(cookies.csrf_passed(&Method::GET, Some(SESSION), None));
+        assert!(cookies.csrf_passed(&Method::HEAD, None, None));
+    }
+
+    #[test]
+    fn compares_whole_values() {
+        assert!(constant_time_eq("abc", "abc"));
+        assert!(!constant_time_eq("abc", "abd"));
+        assert!(!constant_time_eq("abc", "abcd"));
+        assert!(!constant_time_eq("", "a"));
+    }
+}
@@ -0,0 +1,139 @@
+use std::net::{IpAddr, SocketAddr};
+
+use warp::http::HeaderMap;
+
+const FORWARDED: &str = "forwarded";
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// Address of the client behind reverse proxies.
+///
+/// Forwarding headers are believed only when the peer is one of `trusted_proxies`. The chain is walked
+/// from the right skipping trusted proxies, so a client can't spoof its address by sending the header itself.
+/// `Forwarded` (RFC 7239) takes precedence over `X-Forwarded-For`
+pub fn client_address(peer: Option<IpAddr>, headers: &HeaderMap, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+    let peer = peer?;
+    if !trusted_proxies.contains(&peer) {
+        return Some(peer);
+    }
+
+    let chain = forwarded_chain(headers);
+    for hop in chain.iter().rev() {
+        match hop {
+            Some(address) if trusted_proxies.contains(address) => continue,
+            // Obfuscated or malformed hop, whoever is behind it can't be identified
+            hop => return *hop,
+        }
+    }
+
+    // Every hop is a trusted proxy, the request originates from the proxies themselves
+    chain.first().copied().flatten().or(Some(peer))
+}
+
+fn forwarded_chain(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    if headers.contains_key(FORWARDED) {
+        header_elements(headers, FORWARDED)
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, value)| parse_node(value.trim().trim_matches('"')))
+            })
+            .collect()
+    } else {
+        header_elements(headers, X_FORWARDED_FOR)
+            .map(parse_node)
+            .collect()
+    }
+}
+
+/// Comma separated elements of every occurrence of the header, in order
+fn header_elements<'a>(headers: &'a HeaderMap, name: &'static str) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|element| !element.is_empty())
+}
+
+/// Bare address, address with port, or bracketed IPv6 address with optional port
+fn parse_node(node: &str) -> Option<IpAddr> {
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|address| address.ip()))
+        .or_else(|| {
+            node.strip_prefix('[')
+                .and_then(|node| node.strip_suffix(']'))
+                .and_then(|node| node.parse().ok())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::http::HeaderValue;
+
+    const PROXY: &str = "10.0.0.1";
+    const CLIENT: &str = "203.0.113.7";
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    fn headers(entries: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in entries {
+            headers.append(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn ignores_headers_from_untrusted_peer() {
+        let headers = headers(&[(X_FORWARDED_FOR, "198.51.100.1")]);
+        assert_eq!(client_address(Some(ip(CLIENT)), &headers, &[ip(PROXY)]), Some(ip(CLIENT)));
+        assert_eq!(client_address(Some(ip(PROXY)), &headers, &[]), Some(ip(PROXY)));
+    }
+
+    #[test]
+    fn takes_rightmost_untrusted_hop_of_x_forwarded_for() {
+        let headers = headers(&[(X_FORWARDED_FOR, &format!("198.51.100.1, {CLIENT}, 10.0.0.2"))]);
+        let trusted = [ip(PROXY), ip("10.0.0.2")];
+        assert_eq!(client_address(Some(ip(PROXY)), &headers, &trusted), Some(ip(CLIENT)));
+    }
+
+    #[test]
+    fn joins_repeated_headers() {
+        let headers = headers(&[(X_FORWARDED_FOR, "198.51.100.1"), (X_FORWARDED_FOR, CLIENT)]);
+        assert_eq!(client_address(Some(ip(PROXY)), &headers, &[ip(PROXY)]), Some(ip(CLIENT)));
+    }
+
+    #[test]
+    fn prefers_forwarded_header() {
+        let headers = headers(&[
+            (FORWARDED, r#"for=198.51.100.1;proto=https, For="[2001:db8::1]:4711";by=10.0.0.1"#),
+            (X_FORWARDED_FOR, CLIENT),
+        ]);
+        assert_eq!(client_address(Some(ip(PROXY)), &headers, &[ip(PROXY)]), Some(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn parses_addresses_with_ports() {
+        let headers = headers(&[(FORWARDED, "for=203.0.113.7:8443")]);
+        assert_eq!(client_address(Some(ip(PROXY)), &headers, &[ip(PROXY)]), Some(ip(CLIENT)));
+    }
+
+    #[test]
+    fn unknown_hop_hides_the_client() {
+        let headers = headers(&[(FORWARDED, &format!("for={CLIENT}, for=unknown"))]);
+        assert_eq!(client_address(Some(ip(PROXY)), &headers, &[ip(PROXY)]), None);
+    }
+
+    #[test]
+    fn falls_back_to_peer_without_headers() {
+        assert_eq!(client_address(Some(ip(PROXY)), &HeaderMap::new(), &[ip(PROXY)]), Some(ip(PROXY)));
+        assert_eq!(client_address(None, &HeaderMap::new(), &[ip(PROXY)]), None);
+    }
+}
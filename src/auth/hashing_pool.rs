@@ -0,0 +1,64 @@
+use std::sync::Arc;
+
+use log::error;
+use thiserror::Error;
+use tokio::sync::Semaphore;
+
+use crate::config::AuthConfig;
+
+/// Runs CPU-heavy password hashing on blocking threads, so login storms don't stall the executor.
+///
+/// At most `hashing_threads` jobs run at once and at most `hashing_queue_limit` more wait for a thread,
+/// anything beyond that is rejected right away instead of piling up.
+pub(crate) struct HashingPool {
+    admission: Arc<Semaphore>,
+    workers: Arc<Semaphore>,
+}
+
+#[derive(Error, Debug)]
+pub enum HashingPoolError {
+    #[error("Hashing pool is saturated")]
+    Saturated,
+    #[error("Hashing job was interrupted")]
+    Interrupted,
+}
+
+impl HashingPool {
+    pub fn new(auth_config: &AuthConfig) -> Self {
+        Self {
+            admission: Arc::new(Semaphore::new(
+                auth_config.hashing_threads + auth_config.hashing_queue_limit,
+            )),
+            workers: Arc::new(Semaphore::new(auth_config.hashing_threads)),
+        }
+    }
+
+    pub async fn run<T, F>(&self, job: F) -> Result<T, HashingPoolError>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let admission = self
+            .admission
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| HashingPoolError::Saturated)?;
+        let worker = self
+            .workers
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|_| HashingPoolError::Interrupted)?;
+
+        tokio::task::spawn_blocking(move || {
+            // Permits are released only when the job is done, even if the caller is gone
+            let _permits = (admission, worker);
+            job()
+        })
+        .await
+        .map_err(|err| {
+            error!(err:err = err; "Hashing job failed");
+            HashingPoolError::Interrupted
+        })
+    }
+}
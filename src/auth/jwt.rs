@@ -0,0 +1,145 @@
+use std::fs::read;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use moka::sync::Cache;
+use moka::Expiry;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::config::{AuthConfig, JwtAlgorithm};
+
+#[derive(Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: Uuid,
+    pub exp: i64,
+    pub iat: i64,
+    pub jti: String,
+    /// Permissions granted by the user's roles at the moment of issue
+    #[serde(default)]
+    pub permissions: Vec<String>,
+}
+
+/// Issues and locally verifies signed access tokens
+pub(crate) struct JwtCodec {
+    header: Header,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    validation: Validation,
+}
+
+#[derive(Error, Debug)]
+pub enum JwtConfigError {
+    #[error("JWT secret is required for HS256")]
+    MissingSecret,
+    #[error("JWT key files are required for EdDSA")]
+    MissingKeyFiles,
+    #[error("Failed to read JWT key file")]
+    KeyFile(#[from] std::io::Error),
+    #[error("Invalid JWT key")]
+    InvalidKey(#[from] jsonwebtoken::errors::Error),
+}
+
+impl JwtCodec {
+    pub fn new(auth_config: &AuthConfig) -> Result<Self, JwtConfigError> {
+        let (algorithm, encoding_key, decoding_key) = match auth_config.jwt_algorithm {
+            JwtAlgorithm::Hs256 => {
+                let secret = auth_config
+                    .jwt_secret
+                    .as_ref()
+                    .filter(|secret| !secret.is_empty())
+                    .ok_or(JwtConfigError::MissingSecret)?;
+                (
+                    Algorithm::HS256,
+                    EncodingKey::from_secret(secret.as_bytes()),
+                    DecodingKey::from_secret(secret.as_bytes()),
+                )
+            }
+            JwtAlgorithm::EdDsa => {
+                let (Some(private_key_file), Some(public_key_file)) = (
+                    &auth_config.jwt_private_key_file,
+                    &auth_config.jwt_public_key_file,
+                ) else {
+                    return Err(JwtConfigError::MissingKeyFiles);
+                };
+                (
+                    Algorithm::EdDSA,
+                    EncodingKey::from_ed_pem(&read(private_key_file)?)?,
+                    DecodingKey::from_ed_pem(&read(public_key_file)?)?,
+                )
+            }
+        };
+
+        let mut validation = Validation::new(algorithm);
+        validation.leeway = 0;
+        validation.set_required_spec_claims(&["exp", "sub", "jti"]);
+
+        Ok(Self {
+            header: Header::new(algorithm),
+            encoding_key,
+            decoding_key,
+            validation,
+        })
+    }
+
+    pub fn issue(
+        &self,
+        user_id: Uuid,
+        jti: &str,
+        expires: DateTime<Utc>,
+        permissions: &[String],
+    ) -> Result<String, jsonwebtoken::errors::Error> {
+        let claims = Claims {
+            sub: user_id,
+            exp: expires.timestamp(),
+            iat: Utc::now().timestamp(),
+            jti: jti.to_owned(),
+            permissions: permissions.to_vec(),
+        };
+
+        encode(&self.header, &claims, &self.encoding_key)
+    }
+
+    /// Checks signature and expiration, revocation is checked separately
+    pub fn verify(&self, token: &str) -> Option<Claims> {
+        decode::<Claims>(token, &self.decoding_key, &self.validation)
+            .map(|data| data.claims)
+            .ok()
+    }
+}
+
+/// Revoked token ids, every entry is kept until the token itself expires
+pub(crate) struct RevokedTokens {
+    revoked: Cache<String, DateTime<Utc>>,
+}
+
+struct UntilTokenExpires;
+
+impl Expiry<String, DateTime<Utc>> for UntilTokenExpires {
+    fn expire_after_create(
+        &self,
+        _key: &String,
+        value: &DateTime<Utc>,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        Some((*value - Utc::now()).to_std().unwrap_or(Duration::ZERO))
+    }
+}
+
+impl RevokedTokens {
+    pub fn new() -> Self {
+        Self {
+            revoked: Cache::builder().expire_after(UntilTokenExpires).build(),
+        }
+    }
+
+    pub fn revoke(&self, jti: String, expires: DateTime<Utc>) {
+        self.revoked.insert(jti, expires);
+    }
+
+    pub fn is_revoked(&self, jti: &str) -> bool {
+        self.revoked.contains_key(jti)
+    }
+}
@@ -0,0 +1,129 @@
+use std::net::IpAddr;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::config::AuthConfig;
+
+/// Failed login tracking with exponential backoff.
+///
+/// Failures are counted separately per login and, when enabled, per client address,
+/// once a counter reaches its threshold the key is locked for `base * 2^(failures - threshold)`,
+/// capped by `max`. Counters are reset when no failure happened during `window`.
+pub(crate) struct LockoutPolicy {
+    login_threshold: i32,
+    by_address: bool,
+    address_threshold: i32,
+    base: Duration,
+    max: Duration,
+    window: Duration,
+}
+
+pub(crate) enum LockoutKey {
+    Login(String),
+    Address(IpAddr),
+}
+
+impl LockoutKey {
+    pub fn as_key(&self) -> String {
+        match self {
+            LockoutKey::Login(login) => format!("login:{login}"),
+            LockoutKey::Address(address) => format!("address:{address}"),
+        }
+    }
+}
+
+impl LockoutPolicy {
+    pub fn new(auth_config: &AuthConfig) -> Self {
+        Self {
+            login_threshold: auth_config.lockout_login_threshold,
+            by_address: auth_config.lockout_by_address,
+            address_threshold: auth_config.lockout_address_threshold,
+            base: Duration::seconds(auth_config.lockout_base_seconds),
+            max: Duration::seconds(auth_config.lockout_max_seconds),
+            window: Duration::seconds(auth_config.lockout_window_seconds),
+        }
+    }
+
+    pub fn keys(&self, login: &str, address: Option<IpAddr>) -> Vec<LockoutKey> {
+        let mut keys = vec![LockoutKey::Login(login.to_owned())];
+        keys.extend(address.filter(|_| self.by_address).map(LockoutKey::Address));
+        keys
+    }
+
+    /// Failures before this instant don't count anymore
+    pub fn window_start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        now - self.window
+    }
+
+    pub fn lock_duration(&self, key: &LockoutKey, failures: i32) -> Option<Duration> {
+        let threshold = match key {
+            LockoutKey::Login(_) => self.login_threshold,
+            LockoutKey::Address(_) => self.address_threshold,
+        };
+        if failures < threshold {
+            return None;
+        }
+
+        let exponent = (failures - threshold).min(30) as u32;
+        let duration = self
+            .base
+            .checked_mul(2i32.saturating_pow(exponent))
+            .unwrap_or(self.max);
+
+        Some(duration.min(self.max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(by_address: bool) -> LockoutPolicy {
+        LockoutPolicy {
+            login_threshold: 5,
+            by_address,
+            address_threshold: 50,
+            base: Duration::seconds(30),
+            max: Duration::hours(1),
+            window: Duration::minutes(15),
+        }
+    }
+
+    fn login() -> LockoutKey {
+        LockoutKey::Login("alice".to_owned())
+    }
+
+    fn address() -> LockoutKey {
+        LockoutKey::Address("203.0.113.7".parse().unwrap())
+    }
+
+    #[test]
+    fn allows_failures_below_threshold() {
+        assert_eq!(policy(true).lock_duration(&login(), 4), None);
+        assert_eq!(policy(true).lock_duration(&address(), 49), None);
+    }
+
+    #[test]
+    fn doubles_lock_with_each_failure() {
+        assert_eq!(policy(true).lock_duration(&login(), 5), Some(Duration::seconds(30)));
+        assert_eq!(policy(true).lock_duration(&login(), 6), Some(Duration::seconds(60)));
+        assert_eq!(policy(true).lock_duration(&login(), 8), Some(Duration::seconds(240)));
+        assert_eq!(policy(true).lock_duration(&address(), 51), Some(Duration::seconds(60)));
+    }
+
+    #[test]
+    fn caps_lock_at_max() {
+        assert_eq!(policy(true).lock_duration(&login(), 12), Some(Duration::hours(1)));
+        assert_eq!(policy(true).lock_duration(&login(), 40), Some(Duration::hours(1)));
+        assert_eq!(policy(true).lock_duration(&login(), i32::MAX), Some(Duration::hours(1)));
+    }
+
+    #[test]
+    fn tracks_address_only_when_enabled() {
+        let address = "203.0.113.7".parse().ok();
+        let keys: Vec<String> = policy(true).keys("alice", address).iter().map(LockoutKey::as_key).collect();
+        assert_eq!(keys, ["login:alice", "address:203.0.113.7"]);
+        assert_eq!(policy(false).keys("alice", address).len(), 1);
+        assert_eq!(policy(true).keys("alice", None).len(), 1);
+    }
+}
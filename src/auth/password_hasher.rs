@@ -0,0 +1,251 @@
+use std::sync::Arc;
+
+use argon2::password_hash::{PasswordHash, PasswordHasher as _, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use bcrypt::BcryptError;
+use rand::rngs::OsRng;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::config::{AuthConfig, PasswordHasherKind};
+
+pub trait PasswordHasher
+where
+    Self: Send + Sync,
+{
+    /// Whether the hash was produced by this algorithm
+    fn supports(&self, hash: &str) -> bool;
+    fn hash(&self, password: &str) -> Result<String, PasswordHashError>;
+    fn verify(&self, password: &str, hash: &str) -> Result<bool, PasswordHashError>;
+    /// Whether the hash was produced with parameters different from the configured ones
+    fn is_outdated(&self, hash: &str) -> bool;
+}
+
+#[derive(Error, Debug)]
+pub enum PasswordHashError {
+    #[error("Bcrypt error")]
+    Bcrypt(#[from] BcryptError),
+    #[error("Argon2 error")]
+    Argon2(argon2::password_hash::Error),
+    #[error("Unsupported password hash algorithm")]
+    UnsupportedAlgorithm,
+    #[error("Invalid password hasher parameters")]
+    InvalidParameters,
+}
+
+impl From<argon2::password_hash::Error> for PasswordHashError {
+    fn from(value: argon2::password_hash::Error) -> Self {
+        PasswordHashError::Argon2(value)
+    }
+}
+
+pub struct BcryptHasher {
+    cost: u32,
+}
+
+impl PasswordHasher for BcryptHasher {
+    fn supports(&self, hash: &str) -> bool {
+        ["$2a$", "$2b$", "$2x$", "$2y$"]
+            .iter()
+            .any(|prefix| hash.starts_with(prefix))
+    }
+
+    fn hash(&self, password: &str) -> Result<String, PasswordHashError> {
+        Ok(bcrypt::hash(password, self.cost)?)
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> Result<bool, PasswordHashError> {
+        Ok(bcrypt::verify(password, hash)?)
+    }
+
+    fn is_outdated(&self, hash: &str) -> bool {
+        // $2b$12$<salt and hash>
+        hash.split('$')
+            .nth(2)
+            .and_then(|cost| cost.parse::<u32>().ok())
+            .is_none_or(|cost| cost != self.cost)
+    }
+}
+
+pub struct Argon2idHasher {
+    params: Params,
+}
+
+impl Argon2idHasher {
+    fn argon2(&self) -> Argon2<'_> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+}
+
+impl PasswordHasher for Argon2idHasher {
+    fn supports(&self, hash: &str) -> bool {
+        hash.starts_with("$argon2id$")
+    }
+
+    fn hash(&self, password: &str) -> Result<String, PasswordHashError> {
+        let salt = SaltString::generate(&mut OsRng);
+        Ok(self
+            .argon2()
+            .hash_password(password.as_bytes(), &salt)?
+            .to_string())
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> Result<bool, PasswordHashError> {
+        let parsed = PasswordHash::new(hash)?;
+        match self.argon2().verify_password(password.as_bytes(), &parsed) {
+            Ok(()) => Ok(true),
+            Err(argon2::password_hash::Error::Password) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn is_outdated(&self, hash: &str) -> bool {
+        PasswordHash::new(hash)
+            .ok()
+            .filter(|parsed| parsed.version == Some(u32::from(Version::V0x13)))
+            .and_then(|parsed| Params::try_from(&parsed).ok())
+            .is_none_or(|params| {
+                params.m_cost() != self.params.m_cost()
+                    || params.t_cost() != self.params.t_cost()
+                    || params.p_cost() != self.params.p_cost()
+            })
+    }
+}
+
+/// Hashes new passwords with the configured algorithm
+/// and verifies existing hashes with whichever algorithm produced them
+pub struct PasswordHashers {
+    current: Arc<dyn PasswordHasher>,
+    all: Vec<Arc<dyn PasswordHasher>>,
+    dummy_hash: String,
+}
+
+impl PasswordHashers {
+    pub fn new(auth_config: &AuthConfig) -> Result<Self, PasswordHashError> {
+        let bcrypt: Arc<dyn PasswordHasher> = Arc::new(BcryptHasher {
+            cost: auth_config.bcrypt_cost,
+        });
+        let argon2id: Arc<dyn PasswordHasher> = Arc::new(Argon2idHasher {
+            params: Params::new(
+                auth_config.argon2_memory_kib,
+                auth_config.argon2_iterations,
+                auth_config.argon2_parallelism,
+                None,
+            )
+            .map_err(|_| PasswordHashError::InvalidParameters)?,
+        });
+        let current = match auth_config.password_hasher {
+            PasswordHasherKind::Bcrypt => bcrypt.clone(),
+            PasswordHasherKind::Argon2id => argon2id.clone(),
+        };
+
+        Self::with_current(current, vec![bcrypt, argon2id])
+    }
+
+    fn with_current(
+        current: Arc<dyn PasswordHasher>,
+        all: Vec<Arc<dyn PasswordHasher>>,
+    ) -> Result<Self, PasswordHashError> {
+        Ok(Self {
+            dummy_hash: current.hash(&Uuid::new_v4().to_string())?,
+            current,
+            all,
+        })
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String, PasswordHashError> {
+        self.current.hash(password)
+    }
+
+    pub fn verify(&self, password: &str, hash: &str) -> Result<bool, PasswordHashError> {
+        self.all
+            .iter()
+            .find(|hasher| hasher.supports(hash))
+            .ok_or(PasswordHashError::UnsupportedAlgorithm)?
+            .verify(password, hash)
+    }
+
+    /// Hash of a random password produced with the configured parameters.
+    /// Verifying against it costs the same as verifying a real password
+    pub fn dummy_hash(&self) -> &str {
+        &self.dummy_hash
+    }
+
+    /// Whether the hash should be replaced with one produced by the configured algorithm
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        !self.current.supports(hash) || self.current.is_outdated(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bcrypt(cost: u32) -> Arc<dyn PasswordHasher> {
+        Arc::new(BcryptHasher { cost })
+    }
+
+    fn argon2id(memory_kib: u32) -> Arc<dyn PasswordHasher> {
+        Arc::new(Argon2idHasher {
+            params: Params::new(memory_kib, 1, 1, None).unwrap(),
+        })
+    }
+
+    fn hashers(current: Arc<dyn PasswordHasher>) -> PasswordHashers {
+        PasswordHashers::with_current(current, vec![bcrypt(4), argon2id(8)]).unwrap()
+    }
+
+    #[test]
+    fn keeps_hash_of_current_parameters() {
+        for current in [bcrypt(4), argon2id(8)] {
+            let hash = current.hash("password").unwrap();
+            assert!(!hashers(current).needs_rehash(&hash));
+        }
+    }
+
+    #[test]
+    fn rehashes_after_algorithm_change() {
+        let bcrypt_hash = bcrypt(4).hash("password").unwrap();
+        let argon2id_hash = argon2id(8).hash("password").unwrap();
+        assert!(hashers(argon2id(8)).needs_rehash(&bcrypt_hash));
+        assert!(hashers(bcrypt(4)).needs_rehash(&argon2id_hash));
+    }
+
+    #[test]
+    fn rehashes_after_parameter_change() {
+        let bcrypt_hash = bcrypt(4).hash("password").unwrap();
+        let argon2id_hash = argon2id(8).hash("password").unwrap();
+        assert!(hashers(bcrypt(5)).needs_rehash(&bcrypt_hash));
+        assert!(hashers(argon2id(16)).needs_rehash(&argon2id_hash));
+    }
+
+    #[test]
+    fn rehashes_unparsable_hash() {
+        assert!(hashers(bcrypt(4)).needs_rehash("$2b$cost$hash"));
+        assert!(hashers(argon2id(8)).needs_rehash("$argon2id$garbage"));
+    }
+
+    #[test]
+    fn dummy_hash_is_produced_by_current_hasher() {
+        for current in [bcrypt(5), argon2id(16)] {
+            let hashers = hashers(current.clone());
+            assert!(current.supports(hashers.dummy_hash()));
+            assert!(!hashers.needs_rehash(hashers.dummy_hash()));
+        }
+    }
+
+    #[test]
+    fn dummy_hash_uses_configured_cost() {
+        let hashers = hashers(bcrypt(5));
+        assert!(hashers.dummy_hash().starts_with("$2b$05$"));
+    }
+
+    #[test]
+    fn unknown_login_verification_runs_the_hasher() {
+        // An error would skip the hashing work and make unknown logins respond faster
+        for current in [bcrypt(4), argon2id(8)] {
+            let hashers = hashers(current);
+            assert!(!hashers.verify("password", hashers.dummy_hash()).unwrap());
+        }
+    }
+}
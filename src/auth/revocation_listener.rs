@@ -0,0 +1,68 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{error, info, warn};
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+use crate::auth::IDPContext;
+use crate::repo::session_repository::SESSION_REVOCATIONS_CHANNEL;
+
+/// Applies session revocations published by other instances to the local session cache.
+///
+/// Whenever the connection is lost notifications could have been missed,
+/// so the whole cache is dropped and sessions and revoked tokens are re-read from the database.
+pub struct RevocationListener<IDP>
+where
+    IDP: IDPContext<PgPool> + 'static,
+{
+    pub pool: Arc<PgPool>,
+    pub idp: Arc<IDP>,
+    pub reconnect_delay: Duration,
+}
+
+impl<IDP> RevocationListener<IDP>
+where
+    IDP: IDPContext<PgPool> + 'static,
+{
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = self.listen().await {
+                    error!(err:err = err; "Session revocation listener failed");
+                }
+                self.resync().await;
+                sleep(self.reconnect_delay).await;
+            }
+        })
+    }
+
+    async fn listen(&self) -> Result<(), sqlx::Error> {
+        let mut listener = PgListener::connect_with(&self.pool).await?;
+        listener.listen(SESSION_REVOCATIONS_CHANNEL).await?;
+        // Revocations published before LISTEN are unknown to this instance
+        self.resync().await;
+        info!(channel = SESSION_REVOCATIONS_CHANNEL; "Listening for session revocations");
+
+        loop {
+            match listener.try_recv().await? {
+                Some(notification) => {
+                    self.idp.apply_revocation(notification.payload().to_owned());
+                }
+                None => {
+                    // Listener reconnects on the next call
+                    warn!("Lost connection while listening for session revocations");
+                    self.resync().await;
+                }
+            }
+        }
+    }
+
+    async fn resync(&self) {
+        if let Err(err) = self.idp.resync(&self.pool).await {
+            error!(err:err = err; "Failed to resync session revocations");
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use moka::notification::RemovalCause;
+use moka::sync::Cache;
+use moka::Expiry;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::config::AuthConfig;
+
+#[derive(Clone)]
+pub(crate) struct CachedSession {
+    pub user_id: Option<Uuid>,
+    pub created: Option<DateTime<Utc>>,
+    pub expires: Option<DateTime<Utc>>,
+    pub last_seen: Option<DateTime<Utc>>,
+    pub permissions: Vec<String>,
+    pub invalid: bool,
+}
+
+impl CachedSession {
+    pub fn valid(&self) -> bool {
+        !self.invalid && self.expires.map(|time| time > Utc::now()).unwrap_or(false)
+    }
+
+    pub fn invalid() -> Self {
+        CachedSession {
+            user_id: None,
+            created: None,
+            expires: None,
+            last_seen: None,
+            permissions: vec![],
+            invalid: true,
+        }
+    }
+}
+
+/// Bounded W-TinyLFU session cache.
+/// Valid sessions are evicted when they expire, invalid ones after `session_cache_ttl_seconds`
+pub(crate) struct SessionCache {
+    cache: Cache<String, CachedSession>,
+    counters: Arc<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    expirations: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct SessionCacheStats {
+    pub entries: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub expirations: u64,
+}
+
+struct SessionExpiry {
+    max_ttl: Duration,
+}
+
+impl SessionExpiry {
+    fn ttl(&self, session: &CachedSession) -> Option<Duration> {
+        let ttl = match session.expires {
+            Some(expires) if !session.invalid => (expires - Utc::now())
+                .to_std()
+                .unwrap_or(Duration::ZERO),
+            _ => self.max_ttl,
+        };
+
+        Some(ttl.min(self.max_ttl))
+    }
+}
+
+impl Expiry<String, CachedSession> for SessionExpiry {
+    fn expire_after_create(
+        &self,
+        _key: &String,
+        value: &CachedSession,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        self.ttl(value)
+    }
+
+    fn expire_after_update(
+        &self,
+        _key: &String,
+        value: &CachedSession,
+        _updated_at: Instant,
+        _duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        self.ttl(value)
+    }
+}
+
+impl SessionCache {
+    pub fn new(auth_config: &AuthConfig) -> Self {
+        let counters = Arc::new(Counters::default());
+        let listener_counters = counters.clone();
+
+        let cache = Cache::builder()
+            .max_capacity(auth_config.session_cache_capacity)
+            .expire_after(SessionExpiry {
+                max_ttl: Duration::from_secs(auth_config.session_cache_ttl_seconds),
+            })
+            .eviction_listener(move |_, _, cause| match cause {
+                RemovalCause::Size => {
+                    listener_counters.evictions.fetch_add(1, Ordering::Relaxed);
+                }
+                RemovalCause::Expired => {
+                    listener_counters.expirations.fetch_add(1, Ordering::Relaxed);
+                }
+                _ => {}
+            })
+            .build();
+
+        Self { cache, counters }
+    }
+
+    pub fn get(&self, session_id: &str) -> Option<CachedSession> {
+        let cached = self.cache.get(session_id);
+        let counter = if cached.is_some() {
+            &self.counters.hits
+        } else {
+            &self.counters.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        cached
+    }
+
+    pub fn insert(&self, session_id: String, session: CachedSession) {
+        self.cache.insert(session_id, session);
+    }
+
+    pub fn invalidate_all(&self) {
+        self.cache.invalidate_all();
+    }
+
+    pub fn stats(&self) -> SessionCacheStats {
+        SessionCacheStats {
+            entries: self.cache.entry_count(),
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+            evictions: self.counters.evictions.load(Ordering::Relaxed),
+            expirations: self.counters.expirations.load(Ordering::Relaxed),
+        }
+    }
+}
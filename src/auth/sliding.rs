@@ -0,0 +1,93 @@
+use chrono::{DateTime, Duration, Utc};
+
+use crate::config::AuthConfig;
+
+/// Sliding session expiration.
+///
+/// Used session is pushed to `now + lifetime`, but only once `extend_after` of its lifetime has elapsed
+/// since the last extension, so active sessions don't cause a write on every request.
+/// Session never outlives `created + max_lifetime`.
+pub(crate) struct SlidingExpiration {
+    lifetime: Duration,
+    extend_after: Duration,
+    max_lifetime: Duration,
+}
+
+impl SlidingExpiration {
+    /// `None` when sliding expiration is disabled
+    pub fn new(auth_config: &AuthConfig) -> Option<Self> {
+        if !auth_config.session_sliding {
+            return None;
+        }
+
+        let lifetime = Duration::seconds(auth_config.session_lifetime_seconds as i64);
+        let fraction = auth_config.session_extend_after_fraction.clamp(0.0, 1.0);
+        Some(Self {
+            lifetime,
+            extend_after: Duration::milliseconds(
+                (lifetime.num_milliseconds() as f64 * fraction) as i64,
+            ),
+            max_lifetime: Duration::seconds(auth_config.session_max_lifetime_seconds as i64),
+        })
+    }
+
+    /// New expiration time if the session is due for extension
+    pub fn extended_expiry(
+        &self,
+        now: DateTime<Utc>,
+        created: DateTime<Utc>,
+        expires: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        let last_extension = expires - self.lifetime;
+        if now - last_extension < self.extend_after {
+            return None;
+        }
+
+        Some((now + self.lifetime).min(created + self.max_lifetime))
+            .filter(|extended| *extended > expires)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sliding() -> SlidingExpiration {
+        SlidingExpiration {
+            lifetime: Duration::minutes(30),
+            extend_after: Duration::minutes(15),
+            max_lifetime: Duration::hours(2),
+        }
+    }
+
+    fn at(minutes: i64) -> DateTime<Utc> {
+        DateTime::UNIX_EPOCH + Duration::minutes(minutes)
+    }
+
+    #[test]
+    fn keeps_expiry_until_extend_fraction_elapses() {
+        assert_eq!(sliding().extended_expiry(at(10), at(0), at(30)), None);
+    }
+
+    #[test]
+    fn pushes_expiry_to_full_lifetime() {
+        assert_eq!(sliding().extended_expiry(at(20), at(0), at(30)), Some(at(50)));
+    }
+
+    #[test]
+    fn measures_elapsed_time_from_last_extension() {
+        // Extended at 40, expires at 70
+        assert_eq!(sliding().extended_expiry(at(50), at(0), at(70)), None);
+        assert_eq!(sliding().extended_expiry(at(55), at(0), at(70)), Some(at(85)));
+    }
+
+    #[test]
+    fn caps_expiry_at_absolute_lifetime() {
+        assert_eq!(sliding().extended_expiry(at(100), at(0), at(110)), Some(at(120)));
+    }
+
+    #[test]
+    fn stops_extending_at_absolute_lifetime() {
+        assert_eq!(sliding().extended_expiry(at(110), at(0), at(120)), None);
+    }
+}
@@ -0,0 +1,242 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+use subtle::ConstantTimeEq;
+
+const DIGITS: u32 = 6;
+const STEP_SECONDS: i64 = 30;
+const SECRET_BYTES: usize = 20;
+/// Codes of adjacent steps are accepted to tolerate clock drift between client and server
+const ALLOWED_DRIFT_STEPS: i64 = 1;
+const RECOVERY_CODE_BYTES: usize = 8;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Base32 encoded random secret, the format authenticator apps expect
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; SECRET_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    base32_encode(&bytes)
+}
+
+/// Key URI understood by authenticator apps, usually rendered as a QR code
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &str) -> String {
+    let issuer = percent_encode(issuer);
+    let account = percent_encode(account);
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECONDS}"
+    )
+}
+
+/// RFC 6238 code check. Returns time step the code belongs to, so the caller can reject its reuse
+pub fn verify(secret: &str, code: &str, now: DateTime<Utc>) -> Option<i64> {
+    let secret = base32_decode(secret)?;
+    let code = code.trim();
+    if code.len() != DIGITS as usize {
+        return None;
+    }
+
+    let current = now.timestamp().div_euclid(STEP_SECONDS);
+    (current - ALLOWED_DRIFT_STEPS..=current + ALLOWED_DRIFT_STEPS).find(|step| {
+        hotp(&secret, *step, DIGITS)
+            .map(|expected| {
+                let expected = format!("{expected:0width$}", width = DIGITS as usize);
+                expected.as_bytes().ct_eq(code.as_bytes()).into()
+            })
+            .unwrap_or(false)
+    })
+}
+
+/// Single-use codes replacing TOTP when the authenticator is lost, formatted as `xxxx-xxxx-xxxx-xxxx`
+pub fn generate_recovery_codes(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|_| {
+            let mut bytes = [0u8; RECOVERY_CODE_BYTES];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            let code = hex::encode(bytes);
+            format!("{}-{}-{}-{}", &code[0..4], &code[4..8], &code[8..12], &code[12..16])
+        })
+        .collect()
+}
+
+/// Recovery codes are compared ignoring case and separators
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|symbol| symbol.is_ascii_alphanumeric())
+        .map(|symbol| symbol.to_ascii_lowercase())
+        .collect()
+}
+
+/// RFC 4226 HOTP value with dynamic truncation
+fn hotp(secret: &[u8], counter: i64, digits: u32) -> Option<u32> {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).ok()?;
+    mac.update(&(counter as u64).to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset],
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]) & 0x7fff_ffff;
+
+    Some(binary % 10u32.pow(digits))
+}
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    encoded
+}
+
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for symbol in encoded.trim_end_matches('=').bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|candidate| *candidate == symbol.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(decoded)
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+    const RFC_SECRET_BASE32: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    /// RFC 4648 section 10
+    const BASE32_VECTORS: [(&str, &str); 7] = [
+        ("", ""),
+        ("f", "MY======"),
+        ("fo", "MZXQ===="),
+        ("foo", "MZXW6==="),
+        ("foob", "MZXW6YQ="),
+        ("fooba", "MZXW6YTB"),
+        ("foobar", "MZXW6YTBOI======"),
+    ];
+
+    #[test]
+    fn hotp_matches_rfc4226_appendix_d() {
+        let expected = [
+            755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489,
+        ];
+        for (counter, code) in expected.into_iter().enumerate() {
+            assert_eq!(hotp(RFC_SECRET, counter as i64, 6), Some(code), "counter {counter}");
+        }
+    }
+
+    #[test]
+    fn totp_matches_rfc6238_appendix_b() {
+        let expected = [
+            (59, 94287082),
+            (1111111109, 7081804),
+            (1111111111, 14050471),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+            (20000000000, 65353130),
+        ];
+        for (time, code) in expected {
+            let step = time / STEP_SECONDS;
+            assert_eq!(hotp(RFC_SECRET, step, 8), Some(code), "time {time}");
+        }
+    }
+
+    #[test]
+    fn verify_accepts_rfc6238_codes_within_drift() {
+        let now = Utc.timestamp_opt(1111111109, 0).unwrap();
+        let step = now.timestamp() / STEP_SECONDS;
+
+        assert_eq!(verify(RFC_SECRET_BASE32, "081804", now), Some(step));
+        assert_eq!(verify(RFC_SECRET_BASE32, " 081804 ", now), Some(step));
+        assert_eq!(verify(RFC_SECRET_BASE32, "050471", now), Some(step + 1));
+        assert_eq!(verify(RFC_SECRET_BASE32, "081805", now), None);
+        assert_eq!(verify(RFC_SECRET_BASE32, "81804", now), None);
+        assert_eq!(verify(RFC_SECRET_BASE32, "07081804", now), None);
+    }
+
+    #[test]
+    fn verify_rejects_codes_outside_drift() {
+        let now = Utc.timestamp_opt(1111111109 + 3 * STEP_SECONDS, 0).unwrap();
+        assert_eq!(verify(RFC_SECRET_BASE32, "081804", now), None);
+    }
+
+    #[test]
+    fn base32_matches_rfc4648_vectors() {
+        for (plain, encoded) in BASE32_VECTORS {
+            assert_eq!(base32_encode(plain.as_bytes()), encoded.trim_end_matches('='));
+            assert_eq!(base32_decode(encoded).as_deref(), Some(plain.as_bytes()));
+            assert_eq!(
+                base32_decode(encoded.trim_end_matches('=')).as_deref(),
+                Some(plain.as_bytes())
+            );
+        }
+        assert_eq!(base32_encode(RFC_SECRET), RFC_SECRET_BASE32);
+    }
+
+    #[test]
+    fn base32_decode_is_case_insensitive_and_rejects_foreign_symbols() {
+        assert_eq!(
+            base32_decode(&RFC_SECRET_BASE32.to_lowercase()).as_deref(),
+            Some(RFC_SECRET)
+        );
+        assert_eq!(base32_decode("MZXW1==="), None);
+        assert_eq!(base32_decode("MZ=XW6"), None);
+    }
+
+    #[test]
+    fn generated_secret_round_trips() {
+        let secret = generate_secret();
+        let decoded = base32_decode(&secret).unwrap();
+        assert_eq!(decoded.len(), SECRET_BYTES);
+        assert_eq!(base32_encode(&decoded), secret);
+    }
+
+    #[test]
+    fn recovery_codes_normalize_case_and_separators() {
+        let codes = generate_recovery_codes(3);
+        assert_eq!(codes.len(), 3);
+        for code in codes {
+            assert_eq!(code.len(), 19);
+            let normalized = normalize_recovery_code(&code.to_uppercase().replace('-', " "));
+            assert_eq!(normalized, code.replace('-', ""));
+        }
+    }
+}
@@ -1,4 +1,6 @@
 use confique::Config;
+use serde::Deserialize;
+use std::net::IpAddr;
 
 #[derive(Config)]
 pub struct ApplicationConfig {
@@ -8,6 +10,10 @@ pub struct ApplicationConfig {
     pub pg_config: PgConfig,
     #[config(nested)]
     pub auth_config: AuthConfig,
+    #[config(nested)]
+    pub notifier_config: NotifierConfig,
+    #[config(nested)]
+    pub export_config: ExportConfig,
 }
 
 #[derive(Config)]
@@ -23,6 +29,7 @@ pub struct PgConfig {
     pub port: u16,
     #[config(env = "PG_DB")]
     pub database: String,
+    #[allow(dead_code)]
     pub schema: Option<String>,
     #[config(env = "PG_USER")]
     pub user: String,
@@ -32,6 +39,162 @@ pub struct PgConfig {
 
 #[derive(Config)]
 pub struct AuthConfig {
+    pub session_mode: SessionMode,
+    /// Schemes accepted in the `Authorization` header
+    pub authorization_schemes: Vec<AuthorizationScheme>,
     pub session_lifetime_seconds: u32,
-    pub invalid_sessions_cache_limit: usize,
+    /// Extend session on use instead of expiring it at a fixed time
+    pub session_sliding: bool,
+    /// Fraction of the lifetime which has to elapse before the session is extended again
+    pub session_extend_after_fraction: f64,
+    /// Sliding sessions never live longer than that since login
+    pub session_max_lifetime_seconds: u32,
+    /// Minimal interval between persisted updates of the session last seen time
+    pub session_last_seen_interval_seconds: u32,
+    /// Lifetime of a refresh token, each rotation issues a token with full lifetime
+    pub refresh_token_lifetime_seconds: u32,
+    pub session_cache_capacity: u64,
+    pub session_cache_ttl_seconds: u64,
+    pub session_purge_interval_seconds: u64,
+    pub session_purge_batch_size: i64,
+    pub revocation_reconnect_delay_seconds: u64,
+    /// Deleted accounts are kept deactivated for that long before they are erased for good
+    pub account_deletion_grace_period_seconds: u32,
+    pub account_purge_interval_seconds: u64,
+    pub account_purge_batch_size: i64,
+    pub password_hasher: PasswordHasherKind,
+    pub bcrypt_cost: u32,
+    pub argon2_memory_kib: u32,
+    pub argon2_iterations: u32,
+    pub argon2_parallelism: u32,
+    pub hashing_threads: usize,
+    pub hashing_queue_limit: usize,
+    pub password_reset_token_lifetime_seconds: u32,
+    /// Registered accounts stay pending and can't log in until the email is verified
+    pub email_verification_required: bool,
+    pub email_verification_token_lifetime_seconds: u32,
+    /// Address users reach the application at, links in emails are built from it
+    pub public_base_url: String,
+    /// Issuer shown in authenticator apps
+    pub totp_issuer: String,
+    /// Time to enter the second factor after the password was verified
+    pub totp_challenge_lifetime_seconds: u32,
+    pub totp_recovery_codes: usize,
+    pub lockout_login_threshold: i32,
+    /// Also count failures per client address. Behind a reverse proxy every client shares the proxy address
+    /// unless the proxy is listed in `trusted_proxies`
+    pub lockout_by_address: bool,
+    pub lockout_address_threshold: i32,
+    pub lockout_base_seconds: i64,
+    pub lockout_max_seconds: i64,
+    pub lockout_window_seconds: i64,
+    pub jwt_algorithm: JwtAlgorithm,
+    #[config(env = "JWT_SECRET")]
+    pub jwt_secret: Option<String>,
+    pub jwt_private_key_file: Option<String>,
+    pub jwt_public_key_file: Option<String>,
+    /// Additionally accept session token from a cookie set by `/login`, for browser clients
+    pub cookie_sessions: bool,
+    pub cookie_secure: bool,
+    pub cookie_same_site: CookieSameSite,
+    /// Key of the HMAC binding CSRF tokens to sessions, required with `cookie_sessions`
+    #[config(env = "COOKIE_CSRF_SECRET")]
+    pub cookie_csrf_secret: Option<String>,
+    /// Reverse proxies whose `Forwarded` / `X-Forwarded-For` headers carry the client address
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+#[derive(Config)]
+pub struct NotifierConfig {
+    pub kind: NotifierKind,
+    /// Used by [NotifierKind::File]
+    pub directory: String,
+    /// Used by [NotifierKind::Smtp], STARTTLS is required
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    #[config(env = "SMTP_USER")]
+    pub smtp_user: Option<String>,
+    #[config(env = "SMTP_PASS")]
+    pub smtp_password: Option<String>,
+    /// Sender mailbox, e.g. `Social Network <noreply@example.com>`
+    pub smtp_from: String,
+}
+
+#[derive(Config)]
+pub struct ExportConfig {
+    /// Accounts with more entries (interests, sessions, permissions) are exported in background
+    pub sync_item_limit: usize,
+    /// Ready archives can be downloaded for that long
+    pub archive_lifetime_seconds: u32,
+    pub cleanup_interval_seconds: u64,
+    /// Pending exports are also picked up that often, not only when registered by this instance
+    pub archive_interval_seconds: u64,
+    /// Worker has that long to build an archive before another worker may resume the export
+    pub archive_claim_timeout_seconds: u32,
+    pub archive_max_attempts: i32,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum PasswordHasherKind {
+    Bcrypt,
+    Argon2id,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifierKind {
+    Log,
+    File,
+    Smtp,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionMode {
+    Session,
+    Jwt,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub enum AuthorizationScheme {
+    #[serde(rename = "session-id")]
+    SessionId,
+    #[serde(rename = "Bearer")]
+    Bearer,
+}
+
+impl AuthorizationScheme {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AuthorizationScheme::SessionId => "session-id",
+            AuthorizationScheme::Bearer => "Bearer",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum CookieSameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl CookieSameSite {
+    pub fn as_attribute(&self) -> &'static str {
+        match self {
+            CookieSameSite::Strict => "Strict",
+            CookieSameSite::Lax => "Lax",
+            CookieSameSite::None => "None",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub enum JwtAlgorithm {
+    #[serde(rename = "HS256")]
+    Hs256,
+    #[serde(rename = "EdDSA")]
+    EdDsa,
 }
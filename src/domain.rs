@@ -1,4 +1,6 @@
 pub(crate) mod protocol {
+    use warp::http::header::SET_COOKIE;
+    use warp::http::HeaderValue;
     use warp::reject::Reject;
     use warp::{reject, Rejection, Reply};
 
@@ -6,6 +8,24 @@ pub(crate) mod protocol {
         fn into_reply(self) -> impl Reply + 'static;
     }
 
+    /// Reply which additionally sets `cookies`
+    pub struct WithCookies<T> {
+        pub inner: T,
+        pub cookies: Vec<String>,
+    }
+
+    impl<T: ToReply + 'static> ToReply for WithCookies<T> {
+        fn into_reply(self) -> impl Reply + 'static {
+            let mut response = self.inner.into_reply().into_response();
+            for cookie in self.cookies {
+                if let Ok(value) = HeaderValue::from_str(&cookie) {
+                    response.headers_mut().append(SET_COOKIE, value);
+                }
+            }
+            response
+        }
+    }
+
     pub trait ToResponse {
         fn into_response(self) -> Result<Box<dyn Reply>, Rejection>;
     }
@@ -35,14 +55,21 @@ pub(crate) mod protocol {
 
 pub(crate) mod user {
     use chrono::{DateTime, NaiveDate, Utc};
+    use serde::ser::StdError;
     use serde::{Deserialize, Serialize};
     use sqlx::postgres::PgTypeInfo;
     use sqlx::{Decode, Encode, FromRow, Postgres, Type};
+    use std::fmt::Debug;
+    use thiserror::Error;
     use uuid::Uuid;
+    use warp::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE, ETAG, LOCATION};
+    use warp::http::{HeaderValue, StatusCode};
+    use warp::reject::Reject;
     use warp::{reply, Reply};
 
-    use crate::domain::protocol::ToReply;
+    use crate::domain::protocol::{ToReply, WithCookies};
     use crate::domain::user::Gender::Unknown;
+    use crate::export::ArchiveError;
 
     #[derive(Serialize, FromRow, Clone)]
     pub struct User {
@@ -53,9 +80,24 @@ pub(crate) mod user {
         pub gender: Gender,
         pub interests: Vec<Interest>,
         pub city: String,
+        /// Incremented on every update, exposed as `ETag` only
+        #[serde(skip)]
+        pub version: i64,
+    }
+
+    impl User {
+        pub fn etag(&self) -> String {
+            format!("\"{}\"", self.version)
+        }
     }
 
     impl ToReply for User {
+        fn into_reply(self) -> impl Reply {
+            reply::with_header(reply::json(&self), ETAG, self.etag())
+        }
+    }
+
+    impl ToReply for Vec<User> {
         fn into_reply(self) -> impl Reply {
             reply::json(&self)
         }
@@ -78,9 +120,9 @@ pub(crate) mod user {
         }
     }
 
-    impl Into<String> for &Gender {
-        fn into(self) -> String {
-            match self {
+    impl From<&Gender> for String {
+        fn from(gender: &Gender) -> Self {
+            match gender {
                 Gender::Male => "Male".to_owned(),
                 Gender::Female => "Female".to_owned(),
                 _ => "Unknown".to_owned(),
@@ -102,8 +144,104 @@ pub(crate) mod user {
 
     #[derive(Serialize)]
     pub struct AuthenticationResponse {
-        pub(crate) session_id: String,
+        /// Absent when the client opted into cookies, so scripts can't read it
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub(crate) session_id: Option<String>,
         pub(crate) expires: DateTime<Utc>,
+        /// Absent for cookie clients as well, the token is then sent only to the refresh endpoint
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub(crate) refresh_token: Option<String>,
+        pub(crate) refresh_token_expires: DateTime<Utc>,
+    }
+
+    /// Session as shown to its owner, `id` is the hashed session id and can't be used as a credential
+    #[derive(Serialize)]
+    pub struct ActiveSession {
+        pub(crate) id: String,
+        pub(crate) current: bool,
+        pub(crate) created: DateTime<Utc>,
+        pub(crate) expires: DateTime<Utc>,
+        pub(crate) last_seen: DateTime<Utc>,
+        pub(crate) user_agent: Option<String>,
+        pub(crate) client_ip: Option<String>,
+    }
+
+    impl ToReply for Vec<ActiveSession> {
+        fn into_reply(self) -> impl Reply {
+            reply::json(&self)
+        }
+    }
+
+    /// Second step of the login for accounts with TOTP enabled, either `code` or `recovery_code` is required
+    #[derive(Deserialize)]
+    pub struct TotpLoginRequest {
+        pub challenge: String,
+        pub code: Option<String>,
+        pub recovery_code: Option<String>,
+        /// Same as [AuthenticationRequest::use_cookies]
+        #[serde(default)]
+        pub use_cookies: bool,
+    }
+
+    #[derive(Serialize)]
+    pub struct TotpChallengeResponse {
+        pub(crate) challenge: String,
+        pub(crate) challenge_expires: DateTime<Utc>,
+    }
+
+    pub enum LoginResponse {
+        Authenticated(WithCookies<AuthenticationResponse>),
+        ChallengeRequired(TotpChallengeResponse),
+    }
+
+    impl ToReply for LoginResponse {
+        fn into_reply(self) -> impl Reply {
+            match self {
+                LoginResponse::Authenticated(response) => response.into_reply().into_response(),
+                LoginResponse::ChallengeRequired(challenge) => reply::json(&challenge).into_response(),
+            }
+        }
+    }
+
+    #[derive(Serialize)]
+    pub struct TotpEnrollmentResponse {
+        pub(crate) secret: String,
+        pub(crate) otpauth_uri: String,
+    }
+
+    impl ToReply for TotpEnrollmentResponse {
+        fn into_reply(self) -> impl Reply {
+            reply::json(&self)
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct TotpCodeRequest {
+        pub code: String,
+    }
+
+    /// Shown only once, store them somewhere safe
+    #[derive(Serialize)]
+    pub struct RecoveryCodesResponse {
+        pub(crate) recovery_codes: Vec<String>,
+    }
+
+    impl ToReply for RecoveryCodesResponse {
+        fn into_reply(self) -> impl Reply {
+            reply::json(&self)
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct RolesRequest {
+        pub roles: Vec<String>,
+    }
+
+    /// In cookie mode the body may be omitted, the token is then taken from the refresh cookie
+    #[derive(Deserialize, Default)]
+    pub struct RefreshRequest {
+        #[serde(default)]
+        pub refresh_token: Option<String>,
     }
 
     impl ToReply for AuthenticationResponse {
@@ -112,9 +250,78 @@ pub(crate) mod user {
         }
     }
 
+    #[derive(Serialize)]
+    pub struct RevokedSessionsResponse {
+        pub(crate) revoked_sessions: usize,
+    }
+
+    impl ToReply for RevokedSessionsResponse {
+        fn into_reply(self) -> impl Reply {
+            reply::json(&self)
+        }
+    }
+
+    /// Account stays restorable by support until `purge_after`, then it's erased for good
+    #[derive(Serialize)]
+    pub struct AccountDeletionResponse {
+        pub(crate) revoked_sessions: usize,
+        pub(crate) purge_after: DateTime<Utc>,
+    }
+
+    impl ToReply for AccountDeletionResponse {
+        fn into_reply(self) -> impl Reply {
+            reply::with_status(reply::json(&self), StatusCode::ACCEPTED)
+        }
+    }
+
+    /// Either the archive itself or a pointer to the status endpoint while it's built in background
+    pub enum ExportResponse {
+        Ready(Vec<u8>),
+        Pending(Uuid),
+    }
+
+    #[derive(Serialize)]
+    struct ExportStatus {
+        export_id: Uuid,
+        status: &'static str,
+    }
+
+    impl ToReply for ExportResponse {
+        fn into_reply(self) -> impl Reply {
+            match self {
+                ExportResponse::Ready(archive) => {
+                    let mut response = archive.into_response();
+                    let headers = response.headers_mut();
+                    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/zip"));
+                    headers.insert(
+                        CONTENT_DISPOSITION,
+                        HeaderValue::from_static("attachment; filename=\"export.zip\""),
+                    );
+                    response
+                }
+                ExportResponse::Pending(export_id) => {
+                    let mut response = reply::with_status(
+                        reply::json(&ExportStatus {
+                            export_id,
+                            status: "pending",
+                        }),
+                        StatusCode::ACCEPTED,
+                    )
+                    .into_response();
+                    if let Ok(location) = HeaderValue::from_str(&format!("/user/me/export/{export_id}")) {
+                        response.headers_mut().insert(LOCATION, location);
+                    }
+                    response
+                }
+            }
+        }
+    }
+
     #[derive(Deserialize)]
     pub struct RegistrationRequest {
         pub credentials: Credentials,
+        /// Required when email verification is enabled
+        pub email: Option<String>,
         pub first_name: String,
         pub last_name: String,
         pub birth_date: NaiveDate,
@@ -126,6 +333,43 @@ pub(crate) mod user {
     #[derive(Deserialize)]
     pub struct AuthenticationRequest {
         pub credentials: Credentials,
+        /// Hand the tokens over only as cookies, honoured when cookie sessions are enabled
+        #[serde(default)]
+        pub use_cookies: bool,
+    }
+
+    #[derive(Deserialize)]
+    pub struct PasswordChangeRequest {
+        pub old_password: String,
+        pub new_password: String,
+    }
+
+    #[derive(Deserialize)]
+    pub struct PasswordResetRequest {
+        pub login: String,
+    }
+
+    #[derive(Deserialize)]
+    pub struct PasswordResetConfirmation {
+        pub token: String,
+        pub new_password: String,
+    }
+
+    /// Reset is acknowledged the same way whether the login exists or not
+    pub struct PasswordResetAccepted;
+
+    impl ToReply for PasswordResetAccepted {
+        fn into_reply(self) -> impl Reply {
+            reply::with_status(reply::json(&serde_json::json!({})), StatusCode::ACCEPTED)
+        }
+    }
+
+    pub struct EmailVerified;
+
+    impl ToReply for EmailVerified {
+        fn into_reply(self) -> impl Reply {
+            reply::json(&serde_json::json!({}))
+        }
     }
 
     #[derive(Deserialize)]
@@ -133,4 +377,82 @@ pub(crate) mod user {
         pub login: String,
         pub password: String,
     }
+
+    /// Partial profile update, absent fields are left intact and `interests` replace the current ones
+    #[derive(Deserialize)]
+    pub struct UserPatch {
+        pub first_name: Option<String>,
+        pub last_name: Option<String>,
+        pub birth_date: Option<NaiveDate>,
+        pub gender: Option<Gender>,
+        pub city: Option<String>,
+        pub interests: Option<Vec<Interest>>,
+    }
+
+    impl UserPatch {
+        pub fn is_empty(&self) -> bool {
+            self.first_name.is_none()
+                && self.last_name.is_none()
+                && self.birth_date.is_none()
+                && self.gender.is_none()
+                && self.city.is_none()
+                && self.interests.is_none()
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct UserSearchRequest {
+        pub first_name: Option<String>,
+        pub last_name: Option<String>,
+        pub limit: Option<i64>,
+        pub offset: Option<i64>,
+    }
+
+    #[derive(Error, Serialize, Debug)]
+    pub enum UserError<PoolErr: Send + StdError + Sync + 'static> {
+        #[error("Invalid pagination parameters")]
+        InvalidPagination,
+        #[error("Failed to search users")]
+        SearchError(#[serde(skip)] PoolErr),
+        #[error("User not found")]
+        NotFound,
+        #[error("If-Match header with the profile ETag is required")]
+        PreconditionRequired,
+        #[error("Profile was modified concurrently, reload it and retry")]
+        VersionMismatch,
+        #[error("Patch doesn't change any field")]
+        EmptyPatch,
+        #[error("Failed to update user")]
+        UpdateError(#[serde(skip)] PoolErr),
+    }
+
+    impl<T: Debug + Send + StdError + Sync + 'static> Reject for UserError<T> {}
+
+    impl<T: Send + StdError + Sync + 'static> ToReply for UserError<T> {
+        fn into_reply(self) -> impl Reply {
+            reply::with_status(reply::json(&self), StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+
+    #[derive(Error, Serialize, Debug)]
+    pub enum ExportError<PoolErr: Send + StdError + Sync + 'static> {
+        #[error("User not found")]
+        UserNotFound,
+        #[error("Failed to load account data")]
+        DataError(#[serde(skip)] PoolErr),
+        #[error("Export not found or expired")]
+        NotFound,
+        #[error("Export failed, request a new one")]
+        Failed,
+        #[error("Failed to build export archive")]
+        ArchiveError(#[serde(skip)] ArchiveError),
+    }
+
+    impl<T: Debug + Send + StdError + Sync + 'static> Reject for ExportError<T> {}
+
+    impl<T: Send + StdError + Sync + 'static> ToReply for ExportError<T> {
+        fn into_reply(self) -> impl Reply {
+            reply::with_status(reply::json(&self), StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
 }
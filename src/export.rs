@@ -0,0 +1,107 @@
+use std::io::{Cursor, Write};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use thiserror::Error;
+use uuid::Uuid;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::auth::AccountExport;
+use crate::domain::user::{Gender, User};
+
+#[derive(Error, Debug)]
+pub enum ArchiveError {
+    #[error("Failed to serialize export document")]
+    Serialization(#[from] serde_json::Error),
+    #[error("Failed to write archive")]
+    Zip(#[from] zip::result::ZipError),
+}
+
+#[derive(Serialize)]
+struct ProfileDocument<'a> {
+    id: Uuid,
+    first_name: &'a str,
+    last_name: &'a str,
+    birth_date: NaiveDate,
+    gender: &'a Gender,
+    city: &'a str,
+}
+
+#[derive(Serialize)]
+struct SessionDocument<'a> {
+    id: &'a str,
+    created: DateTime<Utc>,
+    expires: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    user_agent: Option<&'a str>,
+    client_ip: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct AuthDocument<'a> {
+    login: &'a str,
+    email: Option<&'a str>,
+    state: &'static str,
+    two_factor_enabled: bool,
+    permissions: &'a [String],
+}
+
+/// Amount of entries the export consists of, used to decide whether to build the archive in background
+pub fn item_count(user: &User, account: &AccountExport) -> usize {
+    user.interests.len() + account.sessions.len() + account.permissions.len()
+}
+
+/// Zip archive with a JSON document per entity
+pub fn build_archive(user: &User, account: &AccountExport) -> Result<Vec<u8>, ArchiveError> {
+    let profile = ProfileDocument {
+        id: user.id,
+        first_name: &user.first_name,
+        last_name: &user.last_name,
+        birth_date: user.birth_date,
+        gender: &user.gender,
+        city: &user.city,
+    };
+    let sessions: Vec<SessionDocument> = account
+        .sessions
+        .iter()
+        .map(|session| SessionDocument {
+            id: &session.session_id,
+            created: session.created,
+            expires: session.expires,
+            last_seen: session.last_seen,
+            user_agent: session.user_agent.as_deref(),
+            client_ip: session.client_ip.as_deref(),
+        })
+        .collect();
+    let auth = AuthDocument {
+        login: &account.login,
+        email: account.email.as_deref(),
+        state: account.state.as_str(),
+        two_factor_enabled: account.two_factor_enabled,
+        permissions: &account.permissions,
+    };
+
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    write_document(&mut writer, "profile.json", &profile)?;
+    write_document(&mut writer, "interests.json", &user.interests)?;
+    write_document(&mut writer, "sessions.json", &sessions)?;
+    write_document(&mut writer, "auth.json", &auth)?;
+
+    Ok(writer.finish()?.into_inner())
+}
+
+fn write_document<T: Serialize + ?Sized>(
+    writer: &mut ZipWriter<Cursor<Vec<u8>>>,
+    name: &str,
+    document: &T,
+) -> Result<(), ArchiveError> {
+    writer.start_file(
+        name,
+        SimpleFileOptions::default().compression_method(CompressionMethod::Deflated),
+    )?;
+    writer
+        .write_all(&serde_json::to_vec_pretty(document)?)
+        .map_err(zip::result::ZipError::Io)?;
+    Ok(())
+}
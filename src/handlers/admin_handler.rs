@@ -0,0 +1,86 @@
+use log::info;
+use std::sync::Arc;
+
+use uuid::Uuid;
+use warp::filters::method;
+use warp::{body, Filter, Rejection, Reply};
+
+use crate::auth::{AuthenticationFilter, IDPContext, IDPError, Principal, USERS_ADMIN_PERMISSION};
+use crate::domain::protocol::ToResponse;
+use crate::domain::user::{RevokedSessionsResponse, RolesRequest};
+use crate::handlers::RestHandler;
+use crate::pool::{DatabasePool, TransactionOps};
+
+/// Endpoints managing other users, every route requires a dedicated permission
+pub struct AdminHandler<IDP, Pool>
+where
+    IDP: IDPContext<Pool>,
+    Pool: DatabasePool,
+{
+    pub pool: Arc<Pool>,
+    pub idp_context: Arc<IDP>,
+    pub authentication_filter: Arc<AuthenticationFilter<Pool, IDP>>,
+}
+
+impl<IDP, Pool> AdminHandler<IDP, Pool>
+where
+    Self: Send + Sync,
+    IDP: IDPContext<Pool>,
+    Pool: DatabasePool,
+{
+    async fn set_roles(
+        &self,
+        principal: Principal,
+        user_id: Uuid,
+        request: RolesRequest,
+    ) -> Result<RevokedSessionsResponse, IDPError<Pool::Err>> {
+        let mut tx = self
+            .pool
+            .begin_tx()
+            .await
+            .map_err(IDPError::RoleChangeError)?;
+        let revoked_sessions = self
+            .idp_context
+            .set_roles(&mut tx, user_id, &request.roles)
+            .await?;
+        tx.commit()
+            .await
+            .map_err(IDPError::RoleChangeError)?;
+
+        info!(
+            user_id:display = user_id,
+            changed_by:display = principal.user_id;
+            "Roles changed by administrator"
+        );
+
+        Ok(RevokedSessionsResponse { revoked_sessions })
+    }
+}
+
+impl<IDP, Pool> RestHandler for Arc<AdminHandler<IDP, Pool>>
+where
+    IDP: IDPContext<Pool>,
+    Pool: DatabasePool,
+{
+    fn routes(self) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+        let handler = self.clone();
+        warp::path!("admin" / "users" / Uuid / "roles")
+            .and(method::put())
+            .and(
+                handler
+                    .authentication_filter
+                    .clone()
+                    .require_permission(USERS_ADMIN_PERMISSION),
+            )
+            .and(body::json())
+            .and_then(move |user_id: Uuid, principal: Principal, request: RolesRequest| {
+                let inner_handler = handler.clone();
+                async move {
+                    inner_handler
+                        .set_roles(principal, user_id, request)
+                        .await
+                        .into_response()
+                }
+            })
+    }
+}
@@ -0,0 +1,154 @@
+use chrono::{Duration, Utc};
+use log::{error, info};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+use uuid::Uuid;
+use warp::filters::method;
+use warp::{Filter, Rejection, Reply};
+
+use crate::auth::{AuthenticationFilter, IDPContext, Principal};
+use crate::domain::protocol::ToResponse;
+use crate::domain::user::{ExportError, ExportResponse};
+use crate::export;
+use crate::handlers::RestHandler;
+use crate::pool::{DatabasePool, TransactionOps};
+use crate::repo::export_repository::{ExportRepository, StoredExport};
+use crate::repo::user_repository::UserRepository;
+
+/// Personal data export. Small accounts get the archive at once, larger ones are archived
+/// by [crate::jobs::export_archive::ExportArchiveJob] and polled through the status endpoint
+pub struct ExportHandler<UserRepo, ExportRepo, IDP, Pool>
+where
+    UserRepo: UserRepository<Pool>,
+    ExportRepo: ExportRepository<Pool>,
+    IDP: IDPContext<Pool>,
+    Pool: DatabasePool,
+{
+    pub pool: Arc<Pool>,
+    pub user_repo: Arc<UserRepo>,
+    pub export_repo: Arc<ExportRepo>,
+    pub idp_context: Arc<IDP>,
+    pub authentication_filter: Arc<AuthenticationFilter<Pool, IDP>>,
+    pub sync_item_limit: usize,
+    pub archive_lifetime: Duration,
+    /// Wakes [crate::jobs::export_archive::ExportArchiveJob] once a background export is registered
+    pub archive_requests: Arc<Notify>,
+}
+
+impl<UserRepo, ExportRepo, IDP, Pool> ExportHandler<UserRepo, ExportRepo, IDP, Pool>
+where
+    Self: Send + Sync,
+    UserRepo: UserRepository<Pool> + 'static,
+    ExportRepo: ExportRepository<Pool> + 'static,
+    IDP: IDPContext<Pool>,
+    Pool: DatabasePool + 'static,
+{
+    async fn export(&self, principal: Principal) -> Result<ExportResponse, ExportError<Pool::Err>> {
+        let mut tx = self
+            .pool
+            .begin_tx()
+            .await
+            .map_err(ExportError::DataError)?;
+        // Repeated requests while the archive is being built must not restart the work
+        if let Some(export_id) = self
+            .export_repo
+            .find_pending(&mut tx, principal.user_id, Utc::now())
+            .await
+            .map_err(ExportError::DataError)?
+        {
+            return Ok(ExportResponse::Pending(export_id));
+        }
+
+        let user = self
+            .user_repo
+            .find(&mut tx, principal.user_id)
+            .await
+            .ok_or(ExportError::UserNotFound)?;
+        let account = self.idp_context.export(&mut tx, principal.user_id).await?;
+
+        if export::item_count(&user, &account) <= self.sync_item_limit {
+            // Compression is CPU bound even for small accounts, it mustn't stall the executor
+            let archive = tokio::task::spawn_blocking(move || export::build_archive(&user, &account))
+                .await
+                .map_err(|err| {
+                    error!(user_id:display = principal.user_id, err:err = err; "Export archiving interrupted");
+                    ExportError::Failed
+                })?
+                .map_err(ExportError::ArchiveError)?;
+            info!(user_id:display = principal.user_id; "Exported personal data");
+            return Ok(ExportResponse::Ready(archive));
+        }
+
+        let export_id = Uuid::new_v4();
+        self.export_repo
+            .create(&mut tx, export_id, principal.user_id, Utc::now() + self.archive_lifetime)
+            .await
+            .map_err(ExportError::DataError)?;
+        tx.commit()
+            .await
+            .map_err(ExportError::DataError)?;
+        self.archive_requests.notify_one();
+        info!(user_id:display = principal.user_id, export_id:display = export_id; "Started personal data export");
+
+        Ok(ExportResponse::Pending(export_id))
+    }
+
+    async fn status(
+        &self,
+        principal: Principal,
+        export_id: Uuid,
+    ) -> Result<ExportResponse, ExportError<Pool::Err>> {
+        let mut tx = self
+            .pool
+            .begin_tx()
+            .await
+            .map_err(ExportError::DataError)?;
+        let stored = self
+            .export_repo
+            .find(&mut tx, export_id, principal.user_id, Utc::now())
+            .await
+            .map_err(ExportError::DataError)?
+            .ok_or(ExportError::NotFound)?;
+
+        match stored {
+            StoredExport::Pending => Ok(ExportResponse::Pending(export_id)),
+            StoredExport::Ready(archive) => Ok(ExportResponse::Ready(archive)),
+            StoredExport::Failed => Err(ExportError::Failed),
+        }
+    }
+}
+
+impl<UserRepo, ExportRepo, IDP, Pool> RestHandler for Arc<ExportHandler<UserRepo, ExportRepo, IDP, Pool>>
+where
+    UserRepo: UserRepository<Pool> + 'static,
+    ExportRepo: ExportRepository<Pool> + 'static,
+    IDP: IDPContext<Pool>,
+    Pool: DatabasePool + 'static,
+{
+    fn routes(self) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+        let export = {
+            let handler = self.clone();
+            warp::path!("user" / "me" / "export")
+                .and(method::get())
+                .and(handler.authentication_filter.clone().with_session())
+                .and_then(move |principal: Principal| {
+                    let inner_handler = handler.clone();
+                    async move { inner_handler.export(principal).await.into_response() }
+                })
+        };
+
+        let status = {
+            let handler = self.clone();
+            warp::path!("user" / "me" / "export" / Uuid)
+                .and(method::get())
+                .and(handler.authentication_filter.clone().with_session())
+                .and_then(move |export_id: Uuid, principal: Principal| {
+                    let inner_handler = handler.clone();
+                    async move { inner_handler.status(principal, export_id).await.into_response() }
+                })
+        };
+
+        export.or(status)
+    }
+}
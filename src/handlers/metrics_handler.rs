@@ -0,0 +1,37 @@
+use std::sync::Arc;
+
+use warp::filters::method;
+use warp::{reply, Filter, Rejection, Reply};
+
+use crate::auth::{AuthenticationFilter, IDPContext, Principal, METRICS_READ_PERMISSION};
+use crate::handlers::RestHandler;
+use crate::pool::DatabasePool;
+
+/// Operational metrics, exposing them requires a dedicated permission
+pub struct MetricsHandler<IDP, Pool>
+where
+    IDP: IDPContext<Pool>,
+    Pool: DatabasePool,
+{
+    pub idp_context: Arc<IDP>,
+    pub authentication_filter: Arc<AuthenticationFilter<Pool, IDP>>,
+}
+
+impl<IDP, Pool> RestHandler for Arc<MetricsHandler<IDP, Pool>>
+where
+    IDP: IDPContext<Pool>,
+    Pool: DatabasePool,
+{
+    fn routes(self) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+        let handler = self.clone();
+        warp::path!("metrics" / "session-cache")
+            .and(method::get())
+            .and(
+                handler
+                    .authentication_filter
+                    .clone()
+                    .require_permission(METRICS_READ_PERMISSION),
+            )
+            .map(move |_: Principal| reply::json(&handler.idp_context.session_cache_stats()))
+    }
+}
@@ -1,5 +1,8 @@
 use warp::Filter;
 
+pub(crate) mod admin_handler;
+pub(crate) mod export_handler;
+pub(crate) mod metrics_handler;
 pub(crate) mod rejection_handler;
 pub(crate) mod user_handler;
 
@@ -1,7 +1,11 @@
-use crate::auth::{AuthenticationError, IDPError};
+use crate::auth::{challenge, AuthenticationError, IDPError};
+use crate::config::AuthorizationScheme;
+use crate::domain::user::{ExportError, UserError};
 use serde::Serialize;
 use std::convert::Infallible;
-use warp::http::StatusCode;
+use std::sync::Arc;
+use warp::http::header::{RETRY_AFTER, WWW_AUTHENTICATE};
+use warp::http::{HeaderMap, HeaderValue, StatusCode};
 use warp::{reply, Rejection, Reply};
 use crate::pool::DatabasePool;
 
@@ -11,9 +15,14 @@ struct ErrorResponse {
     message: String,
 }
 
-pub async fn handle_rejections<Pool: DatabasePool>(err: Rejection) -> Result<impl Reply, Infallible> {
+/// Every `401` carries a `WWW-Authenticate` challenge for each of the accepted `schemes`
+pub async fn handle_rejections<Pool: DatabasePool>(
+    schemes: Arc<Vec<AuthorizationScheme>>,
+    err: Rejection,
+) -> Result<impl Reply, Infallible> {
     let code;
     let message;
+    let mut headers = HeaderMap::new();
 
     if err.is_not_found() {
         code = StatusCode::NOT_FOUND;
@@ -36,10 +45,93 @@ pub async fn handle_rejections<Pool: DatabasePool>(err: Rejection) -> Result<imp
                 code = StatusCode::UNAUTHORIZED;
                 message = e.to_string();
             }
+            IDPError::RevocationError(_) => {
+                code = StatusCode::INTERNAL_SERVER_ERROR;
+                message = e.to_string();
+            }
+            IDPError::PasswordChangeError(_) => {
+                code = StatusCode::INTERNAL_SERVER_ERROR;
+                message = e.to_string();
+            }
+            IDPError::InvalidResetToken => {
+                code = StatusCode::BAD_REQUEST;
+                message = e.to_string();
+            }
+            IDPError::InvalidEmail | IDPError::EmailRequired | IDPError::InvalidVerificationToken => {
+                code = StatusCode::BAD_REQUEST;
+                message = e.to_string();
+            }
+            IDPError::VerificationDeliveryFailed => {
+                code = StatusCode::SERVICE_UNAVAILABLE;
+                message = e.to_string();
+            }
+            IDPError::AccountNotVerified | IDPError::AccountDisabled => {
+                code = StatusCode::FORBIDDEN;
+                message = e.to_string();
+            }
+            IDPError::SessionsError(_) => {
+                code = StatusCode::INTERNAL_SERVER_ERROR;
+                message = e.to_string();
+            }
+            IDPError::SessionNotFound => {
+                code = StatusCode::NOT_FOUND;
+                message = e.to_string();
+            }
+            IDPError::InvalidLoginChallenge | IDPError::InvalidTotpCode => {
+                code = StatusCode::UNAUTHORIZED;
+                message = e.to_string();
+            }
+            IDPError::UnknownUser => {
+                code = StatusCode::NOT_FOUND;
+                message = e.to_string();
+            }
+            IDPError::UnknownRole => {
+                code = StatusCode::BAD_REQUEST;
+                message = e.to_string();
+            }
+            IDPError::AccountDeletionError(_) => {
+                code = StatusCode::INTERNAL_SERVER_ERROR;
+                message = e.to_string();
+            }
+            IDPError::RoleChangeError(_) => {
+                code = StatusCode::INTERNAL_SERVER_ERROR;
+                message = e.to_string();
+            }
+            IDPError::TotpAlreadyEnabled => {
+                code = StatusCode::CONFLICT;
+                message = e.to_string();
+            }
+            IDPError::TotpNotEnrolled => {
+                code = StatusCode::BAD_REQUEST;
+                message = e.to_string();
+            }
+            IDPError::InvalidRefreshToken | IDPError::RefreshTokenReused => {
+                code = StatusCode::UNAUTHORIZED;
+                message = e.to_string();
+            }
+            IDPError::TokenError(_) => {
+                code = StatusCode::INTERNAL_SERVER_ERROR;
+                message = e.to_string();
+            }
+            IDPError::TooManyAttempts {
+                retry_after_seconds,
+            } => {
+                code = StatusCode::TOO_MANY_REQUESTS;
+                message = e.to_string();
+                headers.insert(RETRY_AFTER, HeaderValue::from(*retry_after_seconds));
+            }
             IDPError::CryptoError(_) => {
                 code = StatusCode::INTERNAL_SERVER_ERROR;
                 message = e.to_string();
             }
+            IDPError::Overloaded => {
+                code = StatusCode::SERVICE_UNAVAILABLE;
+                message = e.to_string();
+            }
+            IDPError::HashingFailed => {
+                code = StatusCode::INTERNAL_SERVER_ERROR;
+                message = e.to_string();
+            }
         }
     } else if let Some(e) = err.find::<AuthenticationError>() {
         match e {
@@ -55,16 +147,76 @@ pub async fn handle_rejections<Pool: DatabasePool>(err: Rejection) -> Result<imp
                 code = StatusCode::UNAUTHORIZED;
                 message = e.to_string();
             }
+            AuthenticationError::CsrfTokenMismatch => {
+                code = StatusCode::FORBIDDEN;
+                message = e.to_string();
+            }
+            AuthenticationError::MissingPermission => {
+                code = StatusCode::FORBIDDEN;
+                message = e.to_string();
+            }
         }
+    } else if let Some(e) = err.find::<UserError<Pool::Err>>() {
+        match e {
+            UserError::InvalidPagination | UserError::EmptyPatch => {
+                code = StatusCode::BAD_REQUEST;
+                message = e.to_string();
+            }
+            UserError::SearchError(_) | UserError::UpdateError(_) => {
+                code = StatusCode::INTERNAL_SERVER_ERROR;
+                message = e.to_string();
+            }
+            UserError::NotFound => {
+                code = StatusCode::NOT_FOUND;
+                message = e.to_string();
+            }
+            UserError::PreconditionRequired => {
+                code = StatusCode::PRECONDITION_REQUIRED;
+                message = e.to_string();
+            }
+            UserError::VersionMismatch => {
+                code = StatusCode::PRECONDITION_FAILED;
+                message = e.to_string();
+            }
+        }
+    } else if let Some(e) = err.find::<ExportError<Pool::Err>>() {
+        match e {
+            ExportError::UserNotFound | ExportError::NotFound => {
+                code = StatusCode::NOT_FOUND;
+                message = e.to_string();
+            }
+            ExportError::DataError(_) | ExportError::Failed | ExportError::ArchiveError(_) => {
+                code = StatusCode::INTERNAL_SERVER_ERROR;
+                message = e.to_string();
+            }
+        }
+    } else if let Some(e) = err.find::<warp::reject::InvalidQuery>() {
+        code = StatusCode::BAD_REQUEST;
+        message = e.to_string();
     } else {
         code = StatusCode::INTERNAL_SERVER_ERROR;
         message = "Internal Server Error".to_owned();
     }
 
+    if code == StatusCode::UNAUTHORIZED {
+        let invalid_token = matches!(
+            err.find::<AuthenticationError>(),
+            Some(AuthenticationError::InvalidSessionId)
+        );
+        for scheme in schemes.iter() {
+            if let Ok(value) = HeaderValue::from_str(&challenge(*scheme, invalid_token)) {
+                headers.append(WWW_AUTHENTICATE, value);
+            }
+        }
+    }
+
     let json = reply::json(&ErrorResponse {
         code: code.as_u16(),
         message,
     });
 
-    Ok(reply::with_status(json, code))
+    let mut response = reply::with_status(json, code).into_response();
+    response.headers_mut().extend(headers);
+
+    Ok(response)
 }
@@ -1,21 +1,32 @@
-use log::{error, info};
+use chrono::{Duration, Utc};
+use log::{debug, error, info};
 use std::sync::Arc;
 
 use crate::auth::IDPError::AuthenticationError;
-use crate::auth::{AuthenticationFilter, IDPContext, IDPError};
-use crate::domain::protocol::ToResponse;
+use crate::auth::{
+    AuthenticationFilter, AuthenticationOutcome, ClientInfo, IDPContext, IDPError, IssuedToken,
+    Principal, SecondFactor,
+};
+use crate::domain::protocol::{ToResponse, WithCookies};
 use tap::TapFallible;
 use uuid::Uuid;
 use warp::filters::method;
-use warp::{body, Filter, Rejection, Reply};
+use warp::http::header::IF_MATCH;
+use warp::{body, header, query, Filter, Rejection, Reply};
 
 use crate::domain::user::{
-    AuthenticationRequest, AuthenticationResponse, Credentials, RegistrationRequest, User,
+    AccountDeletionResponse, ActiveSession, AuthenticationRequest, AuthenticationResponse, Credentials, EmailVerified, LoginResponse,
+    PasswordChangeRequest, PasswordResetAccepted, PasswordResetConfirmation, PasswordResetRequest,
+    RecoveryCodesResponse, RefreshRequest, RegistrationRequest, RevokedSessionsResponse,
+    TotpChallengeResponse, TotpCodeRequest, TotpEnrollmentResponse, TotpLoginRequest, User, UserError, UserPatch, UserSearchRequest,
 };
 use crate::handlers::RestHandler;
 use crate::pool::{DatabasePool, TransactionOps};
 use crate::repo::user_repository::UserRepository;
 
+const DEFAULT_SEARCH_LIMIT: i64 = 20;
+const MAX_SEARCH_LIMIT: i64 = 100;
+
 #[derive(Clone)]
 pub struct UserHandler<UserRepo, IDP, Pool>
 where
@@ -27,6 +38,7 @@ where
     pub repository: Arc<UserRepo>,
     pub idp_context: Arc<IDP>,
     pub authentication_filter: Arc<AuthenticationFilter<Pool, IDP>>,
+    pub account_deletion_grace_period: Duration,
 }
 
 impl<UserRepo, IDP, Pool> UserHandler<UserRepo, IDP, Pool>
@@ -36,32 +48,343 @@ where
     UserRepo: UserRepository<Pool>,
     IDP: IDPContext<Pool>,
 {
-    async fn login(&self, credentials: &Credentials) -> Result<AuthenticationResponse, IDPError<Pool::Err>> {
+    /// Tokens are returned in the body, or only as cookies when the client opted into them
+    /// and cookie sessions are enabled
+    fn issued_response(&self, issued: IssuedToken, use_cookies: bool) -> WithCookies<AuthenticationResponse> {
+        let (session_id, refresh_token, cookies) = match &self.authentication_filter.cookies {
+            Some(cookies) if use_cookies => (
+                None,
+                None,
+                cookies.issue(
+                    &issued.token,
+                    issued.expires,
+                    &issued.refresh_token,
+                    issued.refresh_expires,
+                ),
+            ),
+            _ => (Some(issued.token), Some(issued.refresh_token), vec![]),
+        };
+
+        WithCookies {
+            inner: AuthenticationResponse {
+                session_id,
+                expires: issued.expires,
+                refresh_token,
+                refresh_token_expires: issued.refresh_expires,
+            },
+            cookies,
+        }
+    }
+
+    async fn login(
+        &self,
+        credentials: &Credentials,
+        use_cookies: bool,
+        client: &ClientInfo,
+    ) -> Result<LoginResponse, IDPError<Pool::Err>> {
         let mut tx = self
             .pool
             .begin_tx()
             .await
-            .map_err(|err| AuthenticationError(err))?;
-        let response = self
+            .map_err(AuthenticationError)?;
+        let response = match self
             .idp_context
-            .authenticate(&mut tx, &credentials)
+            .authenticate(&mut tx, credentials, client)
             .await
-            .map(|session| AuthenticationResponse {
-                session_id: session.session_id,
-                expires: session.expires,
-            })?;
-        tx.commit().await.map_err(|err| AuthenticationError(err))?;
+        {
+            Ok(AuthenticationOutcome::Authenticated(issued)) => {
+                LoginResponse::Authenticated(self.issued_response(issued, use_cookies))
+            }
+            Ok(AuthenticationOutcome::ChallengeRequired { challenge, expires }) => {
+                LoginResponse::ChallengeRequired(TotpChallengeResponse {
+                    challenge,
+                    challenge_expires: expires,
+                })
+            }
+            Err(IDPError::AuthenticationFailed) => {
+                // Failed attempt has to be persisted for lockout
+                tx.commit().await.map_err(AuthenticationError)?;
+                return Err(IDPError::AuthenticationFailed);
+            }
+            Err(err) => return Err(err),
+        };
+        tx.commit().await.map_err(AuthenticationError)?;
 
         Ok(response)
     }
 
+    async fn login_totp(
+        &self,
+        request: TotpLoginRequest,
+        client: &ClientInfo,
+    ) -> Result<WithCookies<AuthenticationResponse>, IDPError<Pool::Err>> {
+        let second_factor = match (request.code, request.recovery_code) {
+            (Some(code), _) => SecondFactor::Code(code),
+            (None, Some(recovery_code)) => SecondFactor::RecoveryCode(recovery_code),
+            (None, None) => return Err(IDPError::InvalidTotpCode),
+        };
+
+        let mut tx = self
+            .pool
+            .begin_tx()
+            .await
+            .map_err(AuthenticationError)?;
+        let response = match self
+            .idp_context
+            .complete_challenge(&mut tx, &request.challenge, &second_factor, client)
+            .await
+        {
+            Ok(issued) => self.issued_response(issued, request.use_cookies),
+            Err(IDPError::InvalidTotpCode) => {
+                // Consumed challenge has to be persisted, so codes can't be brute-forced
+                tx.commit().await.map_err(AuthenticationError)?;
+                return Err(IDPError::InvalidTotpCode);
+            }
+            Err(err) => return Err(err),
+        };
+        tx.commit().await.map_err(AuthenticationError)?;
+
+        Ok(response)
+    }
+
+    async fn enroll_totp(
+        &self,
+        principal: Principal,
+    ) -> Result<TotpEnrollmentResponse, IDPError<Pool::Err>> {
+        let mut tx = self
+            .pool
+            .begin_tx()
+            .await
+            .map_err(AuthenticationError)?;
+        let enrollment = self.idp_context.enroll_totp(&mut tx, &principal).await?;
+        tx.commit().await.map_err(AuthenticationError)?;
+
+        Ok(TotpEnrollmentResponse {
+            secret: enrollment.secret,
+            otpauth_uri: enrollment.uri,
+        })
+    }
+
+    async fn confirm_totp(
+        &self,
+        principal: Principal,
+        request: TotpCodeRequest,
+    ) -> Result<RecoveryCodesResponse, IDPError<Pool::Err>> {
+        let mut tx = self
+            .pool
+            .begin_tx()
+            .await
+            .map_err(AuthenticationError)?;
+        let recovery_codes = self
+            .idp_context
+            .confirm_totp(&mut tx, &principal, &request.code)
+            .await?;
+        tx.commit().await.map_err(AuthenticationError)?;
+
+        Ok(RecoveryCodesResponse { recovery_codes })
+    }
+
+    /// Token from the body takes precedence over the refresh cookie
+    async fn refresh(
+        &self,
+        request: RefreshRequest,
+        refresh_cookie: Option<String>,
+        client: &ClientInfo,
+    ) -> Result<WithCookies<AuthenticationResponse>, IDPError<Pool::Err>> {
+        // Token from the cookie is rotated into cookies again
+        let (refresh_token, use_cookies) = match (request.refresh_token, refresh_cookie) {
+            (Some(refresh_token), _) => (refresh_token, false),
+            (None, Some(refresh_token)) => (refresh_token, true),
+            (None, None) => return Err(IDPError::InvalidRefreshToken),
+        };
+        let mut tx = self
+            .pool
+            .begin_tx()
+            .await
+            .map_err(AuthenticationError)?;
+        let response = match self
+            .idp_context
+            .refresh(&mut tx, &refresh_token, client)
+            .await
+        {
+            Ok(issued) => self.issued_response(issued, use_cookies),
+            Err(
+                err @ (IDPError::RefreshTokenReused
+                | IDPError::InvalidRefreshToken
+                | IDPError::AccountDisabled
+                | IDPError::AccountNotVerified),
+            ) => {
+                // Revocation of the token family has to be persisted
+                tx.commit().await.map_err(AuthenticationError)?;
+                return Err(err);
+            }
+            Err(err) => return Err(err),
+        };
+        tx.commit().await.map_err(AuthenticationError)?;
+
+        Ok(response)
+    }
+
+    async fn sessions(
+        &self,
+        principal: Principal,
+    ) -> Result<Vec<ActiveSession>, IDPError<Pool::Err>> {
+        let mut tx = self
+            .pool
+            .begin_tx()
+            .await
+            .map_err(IDPError::SessionsError)?;
+        let sessions = self.idp_context.sessions(&mut tx, &principal).await?;
+
+        Ok(sessions
+            .into_iter()
+            .map(|session| ActiveSession {
+                current: session.session_id == principal.session_id,
+                id: session.session_id,
+                created: session.created,
+                expires: session.expires,
+                last_seen: session.last_seen,
+                user_agent: session.user_agent,
+                client_ip: session.client_ip,
+            })
+            .collect())
+    }
+
+    async fn revoke_session(
+        &self,
+        principal: Principal,
+        session_id: String,
+    ) -> Result<RevokedSessionsResponse, IDPError<Pool::Err>> {
+        let mut tx = self
+            .pool
+            .begin_tx()
+            .await
+            .map_err(IDPError::RevocationError)?;
+        let revoked_sessions = self
+            .idp_context
+            .revoke_own(&mut tx, &principal, &session_id)
+            .await?;
+        tx.commit()
+            .await
+            .map_err(IDPError::RevocationError)?;
+
+        info!(
+            user_id:display = principal.user_id,
+            revoked_sessions = revoked_sessions;
+            "Revoked session"
+        );
+
+        Ok(RevokedSessionsResponse { revoked_sessions })
+    }
+
+    async fn logout(
+        &self,
+        principal: Principal,
+        everywhere: bool,
+    ) -> Result<WithCookies<RevokedSessionsResponse>, IDPError<Pool::Err>> {
+        let mut tx = self
+            .pool
+            .begin_tx()
+            .await
+            .map_err(IDPError::RevocationError)?;
+        let revoked_sessions = if everywhere {
+            self.idp_context.revoke_all(&mut tx, &principal).await?
+        } else {
+            self.idp_context.revoke(&mut tx, &principal.session_id).await?
+        };
+        tx.commit()
+            .await
+            .map_err(IDPError::RevocationError)?;
+
+        info!(
+            user_id:display = principal.user_id,
+            revoked_sessions = revoked_sessions;
+            "Logged out"
+        );
+
+        Ok(WithCookies {
+            inner: RevokedSessionsResponse { revoked_sessions },
+            cookies: self
+                .authentication_filter
+                .cookies
+                .as_ref()
+                .map(|cookies| cookies.clear())
+                .unwrap_or_default(),
+        })
+    }
+
+    async fn change_password(
+        &self,
+        principal: Principal,
+        request: PasswordChangeRequest,
+    ) -> Result<RevokedSessionsResponse, IDPError<Pool::Err>> {
+        let mut tx = self
+            .pool
+            .begin_tx()
+            .await
+            .map_err(IDPError::PasswordChangeError)?;
+        let revoked_sessions = self
+            .idp_context
+            .change_password(
+                &mut tx,
+                &principal,
+                &request.old_password,
+                &request.new_password,
+            )
+            .await?;
+        tx.commit()
+            .await
+            .map_err(IDPError::PasswordChangeError)?;
+
+        Ok(RevokedSessionsResponse { revoked_sessions })
+    }
+
+    async fn request_password_reset(
+        &self,
+        request: PasswordResetRequest,
+    ) -> Result<PasswordResetAccepted, IDPError<Pool::Err>> {
+        let mut tx = self
+            .pool
+            .begin_tx()
+            .await
+            .map_err(IDPError::PasswordChangeError)?;
+        self.idp_context
+            .request_password_reset(&mut tx, &request.login)
+            .await?;
+        tx.commit()
+            .await
+            .map_err(IDPError::PasswordChangeError)?;
+
+        Ok(PasswordResetAccepted)
+    }
+
+    async fn reset_password(
+        &self,
+        confirmation: PasswordResetConfirmation,
+    ) -> Result<RevokedSessionsResponse, IDPError<Pool::Err>> {
+        let mut tx = self
+            .pool
+            .begin_tx()
+            .await
+            .map_err(IDPError::PasswordChangeError)?;
+        let revoked_sessions = self
+            .idp_context
+            .reset_password(&mut tx, &confirmation.token, &confirmation.new_password)
+            .await?;
+        tx.commit()
+            .await
+            .map_err(IDPError::PasswordChangeError)?;
+
+        Ok(RevokedSessionsResponse { revoked_sessions })
+    }
+
     async fn register(&self, request: RegistrationRequest) -> Result<User, IDPError<Pool::Err>> {
         let credentials = request.credentials;
         let mut tx = self
             .pool
             .begin_tx()
             .await
-            .map_err(|err| IDPError::RegistrationError(err))?;
+            .map_err(IDPError::RegistrationError)?;
 
         let user = User {
             id: Uuid::new_v4(),
@@ -71,35 +394,165 @@ where
             gender: request.gender,
             interests: request.interests,
             city: request.city,
+            version: 1,
         };
 
-        let _ = self
+        self
             .repository
             .save(&mut tx, user.clone())
             .await
-            .map_err(|err| IDPError::RegistrationError(err))?;
+            .map_err(IDPError::RegistrationError)?;
         self.idp_context
-            .add_user(&mut tx, &credentials.login, &credentials.password, &user)
+            .add_user(
+                &mut tx,
+                &credentials.login,
+                &credentials.password,
+                request.email.as_deref(),
+                &user,
+            )
             .await?;
 
         tx.commit()
             .await
-            .map_err(|err| IDPError::RegistrationError(err))?;
+            .map_err(IDPError::RegistrationError)?;
 
         info!(user_id:display = user.id.clone(), login = credentials.login.clone(); "Registered new user");
 
         Ok(user)
     }
 
-    async fn get(&self, user_id: Uuid) -> Option<User> {
+    async fn verify_email(&self, token: String) -> Result<EmailVerified, IDPError<Pool::Err>> {
+        let mut tx = self
+            .pool
+            .begin_tx()
+            .await
+            .map_err(IDPError::RegistrationError)?;
+        self.idp_context.verify_email(&mut tx, &token).await?;
+        tx.commit()
+            .await
+            .map_err(IDPError::RegistrationError)?;
+
+        Ok(EmailVerified)
+    }
+
+    /// Soft delete, the account is erased by [crate::jobs::account_purge::AccountPurgeJob] after the grace period
+    async fn delete_account(
+        &self,
+        principal: Principal,
+    ) -> Result<WithCookies<AccountDeletionResponse>, IDPError<Pool::Err>> {
+        let deleted_at = Utc::now();
+        let mut tx = self
+            .pool
+            .begin_tx()
+            .await
+            .map_err(IDPError::AccountDeletionError)?;
+        self.repository
+            .mark_deleted(&mut tx, principal.user_id, deleted_at)
+            .await
+            .map_err(IDPError::AccountDeletionError)?;
+        let revoked_sessions = self.idp_context.deactivate(&mut tx, &principal).await?;
+        tx.commit()
+            .await
+            .map_err(IDPError::AccountDeletionError)?;
+
+        info!(user_id:display = principal.user_id; "Deleted account");
+
+        Ok(WithCookies {
+            inner: AccountDeletionResponse {
+                revoked_sessions,
+                purge_after: deleted_at + self.account_deletion_grace_period,
+            },
+            cookies: self
+                .authentication_filter
+                .cookies
+                .as_ref()
+                .map(|cookies| cookies.clear())
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Optimistic concurrency: the patch applies only if `If-Match` carries the current profile `ETag`
+    async fn update(
+        &self,
+        principal: Principal,
+        if_match: Option<String>,
+        patch: UserPatch,
+    ) -> Result<User, UserError<Pool::Err>> {
+        // Would bump the version and invalidate ETags of other clients without changing anything
+        if patch.is_empty() {
+            return Err(UserError::EmptyPatch);
+        }
+        let expected_versions = match if_match.as_deref().map(parse_if_match) {
+            None => return Err(UserError::PreconditionRequired),
+            Some(versions) => versions,
+        };
+
+        let mut tx = self
+            .pool
+            .begin_tx()
+            .await
+            .map_err(UserError::UpdateError)?;
+        let updated = self
+            .repository
+            .update(&mut tx, principal.user_id, expected_versions.as_deref(), patch)
+            .await
+            .map_err(UserError::UpdateError)?;
+        if updated.is_none() {
+            return Err(match self.repository.find(&mut tx, principal.user_id).await {
+                Some(_) => UserError::VersionMismatch,
+                None => UserError::NotFound,
+            });
+        }
+        let user = self
+            .repository
+            .find(&mut tx, principal.user_id)
+            .await
+            .ok_or(UserError::NotFound)?;
+        tx.commit().await.map_err(UserError::UpdateError)?;
+
+        info!(user_id:display = user.id, version = user.version; "Updated user");
+
+        Ok(user)
+    }
+
+    async fn get(&self, principal: &Principal, user_id: Uuid) -> Option<User> {
         let mut tx = self.pool.begin_tx().await.ok()?;
         let user = self.repository.find(&mut tx, user_id).await;
+        debug!(user_id:display = user_id, requested_by:display = principal.user_id; "Fetched user");
         let _ = tx
             .commit()
             .await
             .tap_err(|err| error!(err:err = *err; "Failed to find user"));
         user
     }
+
+    async fn search(&self, request: UserSearchRequest) -> Result<Vec<User>, UserError<Pool::Err>> {
+        let limit = request.limit.unwrap_or(DEFAULT_SEARCH_LIMIT);
+        let offset = request.offset.unwrap_or(0);
+        if !(1..=MAX_SEARCH_LIMIT).contains(&limit) || offset < 0 {
+            return Err(UserError::InvalidPagination);
+        }
+
+        let mut tx = self
+            .pool
+            .begin_tx()
+            .await
+            .map_err(UserError::SearchError)?;
+        let users = self
+            .repository
+            .search(
+                &mut tx,
+                request.first_name.as_deref().unwrap_or_default(),
+                request.last_name.as_deref().unwrap_or_default(),
+                limit,
+                offset,
+            )
+            .await
+            .map_err(UserError::SearchError)?;
+        tx.commit().await.map_err(UserError::SearchError)?;
+
+        Ok(users)
+    }
 }
 
 impl<UserRepo, IDP, Pool> RestHandler for Arc<UserHandler<UserRepo, IDP, Pool>>
@@ -114,11 +567,187 @@ where
             warp::path!("login")
                 .and(method::post())
                 .and(body::json())
-                .and_then(move |authentication: AuthenticationRequest| {
+                .and(handler.authentication_filter.clone().client_info())
+                .and_then(move |authentication: AuthenticationRequest, client: ClientInfo| {
                     let inner_handler = handler.clone();
                     async move {
                         inner_handler
-                            .login(&authentication.credentials)
+                            .login(&authentication.credentials, authentication.use_cookies, &client)
+                            .await
+                            .into_response()
+                    }
+                })
+        };
+
+        let login_totp = {
+            let handler = self.clone();
+            warp::path!("login" / "totp")
+                .and(method::post())
+                .and(body::json())
+                .and(handler.authentication_filter.clone().client_info())
+                .and_then(move |request: TotpLoginRequest, client: ClientInfo| {
+                    let inner_handler = handler.clone();
+                    async move {
+                        inner_handler
+                            .login_totp(request, &client)
+                            .await
+                            .into_response()
+                    }
+                })
+        };
+
+        let enroll_totp = {
+            let handler = self.clone();
+            warp::path!("user" / "totp" / "enroll")
+                .and(method::post())
+                .and(handler.authentication_filter.clone().with_session())
+                .and_then(move |principal: Principal| {
+                    let inner_handler = handler.clone();
+                    async move { inner_handler.enroll_totp(principal).await.into_response() }
+                })
+        };
+
+        let confirm_totp = {
+            let handler = self.clone();
+            warp::path!("user" / "totp" / "verify")
+                .and(method::post())
+                .and(handler.authentication_filter.clone().with_session())
+                .and(body::json())
+                .and_then(move |principal: Principal, request: TotpCodeRequest| {
+                    let inner_handler = handler.clone();
+                    async move {
+                        inner_handler
+                            .confirm_totp(principal, request)
+                            .await
+                            .into_response()
+                    }
+                })
+        };
+
+        let refresh = {
+            let handler = self.clone();
+            warp::path!("token" / "refresh")
+                .and(method::post())
+                .and(
+                    body::json()
+                        .or(warp::any().map(RefreshRequest::default))
+                        .unify(),
+                )
+                .and(handler.authentication_filter.clone().refresh_cookie())
+                .and(handler.authentication_filter.clone().client_info())
+                .and_then(move |request: RefreshRequest, refresh_cookie, client: ClientInfo| {
+                    let inner_handler = handler.clone();
+                    async move {
+                        inner_handler
+                            .refresh(request, refresh_cookie, &client)
+                            .await
+                            .into_response()
+                    }
+                })
+        };
+
+        let sessions = {
+            let handler = self.clone();
+            warp::path!("sessions")
+                .and(method::get())
+                .and(handler.authentication_filter.clone().with_session())
+                .and_then(move |principal: Principal| {
+                    let inner_handler = handler.clone();
+                    async move { inner_handler.sessions(principal).await.into_response() }
+                })
+        };
+
+        let revoke_session = {
+            let handler = self.clone();
+            warp::path!("sessions" / String)
+                .and(method::delete())
+                .and(handler.authentication_filter.clone().with_session())
+                .and_then(move |session_id: String, principal: Principal| {
+                    let inner_handler = handler.clone();
+                    async move {
+                        inner_handler
+                            .revoke_session(principal, session_id)
+                            .await
+                            .into_response()
+                    }
+                })
+        };
+
+        let logout = {
+            let handler = self.clone();
+            warp::path!("logout")
+                .and(method::post())
+                .and(handler.authentication_filter.clone().with_session())
+                .and_then(move |principal: Principal| {
+                    let inner_handler = handler.clone();
+                    async move {
+                        inner_handler
+                            .logout(principal, false)
+                            .await
+                            .into_response()
+                    }
+                })
+        };
+
+        let logout_everywhere = {
+            let handler = self.clone();
+            warp::path!("logout" / "all")
+                .and(method::post())
+                .and(handler.authentication_filter.clone().with_session())
+                .and_then(move |principal: Principal| {
+                    let inner_handler = handler.clone();
+                    async move {
+                        inner_handler
+                            .logout(principal, true)
+                            .await
+                            .into_response()
+                    }
+                })
+        };
+
+        let change_password = {
+            let handler = self.clone();
+            warp::path!("user" / "password")
+                .and(method::post())
+                .and(handler.authentication_filter.clone().with_session())
+                .and(body::json())
+                .and_then(move |principal: Principal, request: PasswordChangeRequest| {
+                    let inner_handler = handler.clone();
+                    async move {
+                        inner_handler
+                            .change_password(principal, request)
+                            .await
+                            .into_response()
+                    }
+                })
+        };
+
+        let request_password_reset = {
+            let handler = self.clone();
+            warp::path!("user" / "password" / "reset")
+                .and(method::post())
+                .and(body::json())
+                .and_then(move |request: PasswordResetRequest| {
+                    let inner_handler = handler.clone();
+                    async move {
+                        inner_handler
+                            .request_password_reset(request)
+                            .await
+                            .into_response()
+                    }
+                })
+        };
+
+        let reset_password = {
+            let handler = self.clone();
+            warp::path!("user" / "password" / "reset" / "confirm")
+                .and(method::post())
+                .and(body::json())
+                .and_then(move |confirmation: PasswordResetConfirmation| {
+                    let inner_handler = handler.clone();
+                    async move {
+                        inner_handler
+                            .reset_password(confirmation)
                             .await
                             .into_response()
                     }
@@ -141,16 +770,135 @@ where
                 })
         };
 
+        let verify_email = {
+            let handler = self.clone();
+            warp::path!("user" / "verify" / String)
+                .and(method::get())
+                .and_then(move |token: String| {
+                    let inner_handler = handler.clone();
+                    async move { inner_handler.verify_email(token).await.into_response() }
+                })
+        };
+
+        let update = {
+            let handler = self.clone();
+            warp::path!("user" / "me")
+                .and(method::patch())
+                .and(handler.authentication_filter.clone().with_session())
+                .and(header::optional::<String>(IF_MATCH.as_str()))
+                .and(body::json())
+                .and_then(move |principal: Principal, if_match, patch| {
+                    let inner_handler = handler.clone();
+                    async move {
+                        inner_handler
+                            .update(principal, if_match, patch)
+                            .await
+                            .into_response()
+                    }
+                })
+        };
+
+        let delete_account = {
+            let handler = self.clone();
+            warp::path!("user" / "me")
+                .and(method::delete())
+                .and(handler.authentication_filter.clone().with_session())
+                .and_then(move |principal: Principal| {
+                    let inner_handler = handler.clone();
+                    async move { inner_handler.delete_account(principal).await.into_response() }
+                })
+        };
+
         let get = {
             let handler = self.clone();
             warp::path!("user" / "get" / Uuid)
                 .and(handler.authentication_filter.clone().with_session())
-                .and_then(move |user_id, _| {
+                .and_then(move |user_id, principal: Principal| {
                     let inner_handler = handler.clone();
-                    async move { inner_handler.get(user_id).await.into_response() }
+                    async move { inner_handler.get(&principal, user_id).await.into_response() }
                 })
         };
 
-        login.or(register).or(get)
+        let search = {
+            let handler = self.clone();
+            warp::path!("user" / "search")
+                .and(method::get())
+                .and(query::<UserSearchRequest>())
+                .and(handler.authentication_filter.clone().with_session())
+                .and_then(move |search_request, _| {
+                    let inner_handler = handler.clone();
+                    async move { inner_handler.search(search_request).await.into_response() }
+                })
+        };
+
+        login
+            .or(login_totp)
+            .or(enroll_totp)
+            .or(confirm_totp)
+            .or(refresh)
+            .or(sessions)
+            .or(revoke_session)
+            .or(logout)
+            .or(logout_everywhere)
+            .or(change_password)
+            .or(request_password_reset)
+            .or(reset_password)
+            .or(register)
+            .or(verify_email)
+            .or(update)
+            .or(delete_account)
+            .or(get)
+            .or(search)
+    }
+}
+
+/// Versions listed in `If-Match`, `None` for `*` which matches any version.
+/// Weak tags never match as `If-Match` requires strong comparison
+fn parse_if_match(header: &str) -> Option<Vec<i64>> {
+    if header.trim() == "*" {
+        return None;
+    }
+
+    Some(
+        header
+            .split(',')
+            .filter_map(|tag| {
+                tag.trim()
+                    .strip_prefix('"')
+                    .and_then(|tag| tag.strip_suffix('"'))
+                    .and_then(|version| version.parse().ok())
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn star_matches_any_version() {
+        assert_eq!(parse_if_match("*"), None);
+        assert_eq!(parse_if_match(" * "), None);
+    }
+
+    #[test]
+    fn parses_quoted_versions() {
+        assert_eq!(parse_if_match(r#""3""#), Some(vec![3]));
+        assert_eq!(parse_if_match(r#""3", "4""#), Some(vec![3, 4]));
+    }
+
+    #[test]
+    fn weak_tags_never_match() {
+        assert_eq!(parse_if_match(r#"W/"3""#), Some(vec![]));
+        assert_eq!(parse_if_match(r#"W/"3", "4""#), Some(vec![4]));
+    }
+
+    #[test]
+    fn skips_malformed_tags() {
+        assert_eq!(parse_if_match("3"), Some(vec![]));
+        assert_eq!(parse_if_match(r#""three", "3"#), Some(vec![]));
+        assert_eq!(parse_if_match(r#""", "5""#), Some(vec![5]));
+        assert_eq!(parse_if_match(""), Some(vec![]));
     }
 }
@@ -0,0 +1,65 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use log::{error, info};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, MissedTickBehavior};
+
+use crate::pool::{DatabasePool, TransactionOps};
+use crate::repo::user_repository::UserRepository;
+
+/// Periodically erases accounts deleted longer than `grace_period` ago.
+/// Rows referencing the user are removed by cascading foreign keys
+pub struct AccountPurgeJob<Pool, UserRepo>
+where
+    Pool: DatabasePool + 'static,
+    UserRepo: UserRepository<Pool> + 'static,
+{
+    pub pool: Arc<Pool>,
+    pub user_repo: Arc<UserRepo>,
+    pub interval: Duration,
+    pub grace_period: chrono::Duration,
+    pub batch_size: i64,
+}
+
+impl<Pool, UserRepo> AccountPurgeJob<Pool, UserRepo>
+where
+    Pool: DatabasePool + 'static,
+    UserRepo: UserRepository<Pool> + 'static,
+{
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = interval(self.interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                ticker.tick().await;
+                match self.purge().await {
+                    Ok(0) => {}
+                    Ok(purged) => info!(purged = purged; "Erased deleted accounts"),
+                    Err(err) => error!(err:err = err; "Failed to erase deleted accounts"),
+                }
+            }
+        })
+    }
+
+    async fn purge(&self) -> Result<u64, Pool::Err> {
+        let before = Utc::now() - self.grace_period;
+        let mut purged = 0;
+
+        loop {
+            let mut tx = self.pool.begin_tx().await?;
+            let deleted = self
+                .user_repo
+                .purge_deleted(&mut tx, before, self.batch_size)
+                .await?;
+            tx.commit().await?;
+
+            purged += deleted;
+            if deleted < self.batch_size as u64 {
+                return Ok(purged);
+            }
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use log::{error, info, warn};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, MissedTickBehavior};
+use uuid::Uuid;
+
+use crate::auth::IDPContext;
+use crate::domain::user::ExportError;
+use crate::export;
+use crate::pool::{DatabasePool, TransactionOps};
+use crate::repo::export_repository::{ClaimedExport, ExportRepository};
+use crate::repo::user_repository::UserRepository;
+
+/// Builds archives of pending data exports. Runs when a local handler registers an export
+/// and every `interval`, so exports claimed by a crashed instance are resumed once their claim expires.
+/// Exports still pending after `max_attempts` claims are marked failed
+pub struct ExportArchiveJob<Pool, UserRepo, ExportRepo, IDP>
+where
+    Pool: DatabasePool + 'static,
+    UserRepo: UserRepository<Pool> + 'static,
+    ExportRepo: ExportRepository<Pool> + 'static,
+    IDP: IDPContext<Pool> + 'static,
+{
+    pub pool: Arc<Pool>,
+    pub user_repo: Arc<UserRepo>,
+    pub export_repo: Arc<ExportRepo>,
+    pub idp_context: Arc<IDP>,
+    pub requests: Arc<Notify>,
+    pub interval: Duration,
+    pub claim_timeout: chrono::Duration,
+    pub max_attempts: i32,
+}
+
+impl<Pool, UserRepo, ExportRepo, IDP> ExportArchiveJob<Pool, UserRepo, ExportRepo, IDP>
+where
+    Pool: DatabasePool + 'static,
+    UserRepo: UserRepository<Pool> + 'static,
+    ExportRepo: ExportRepository<Pool> + 'static,
+    IDP: IDPContext<Pool> + 'static,
+{
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = interval(self.interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = self.requests.notified() => {}
+                }
+                match self.archive_pending().await {
+                    Ok(0) => {}
+                    Ok(archived) => info!(archived = archived; "Archived pending data exports"),
+                    Err(err) => error!(err:err = err; "Failed to archive pending data exports"),
+                }
+            }
+        })
+    }
+
+    async fn archive_pending(&self) -> Result<u64, Pool::Err> {
+        let mut tx = self.pool.begin_tx().await?;
+        let failed = self
+            .export_repo
+            .fail_exhausted(&mut tx, Utc::now(), self.max_attempts)
+            .await?;
+        tx.commit().await?;
+        if failed > 0 {
+            warn!(failed = failed; "Gave up on data exports after repeated attempts");
+        }
+
+        let mut archived = 0;
+        loop {
+            let now = Utc::now();
+            let mut tx = self.pool.begin_tx().await?;
+            let claimed = self
+                .export_repo
+                .claim(&mut tx, now, now + self.claim_timeout, self.max_attempts)
+                .await?;
+            tx.commit().await?;
+
+            let Some(claimed) = claimed else {
+                return Ok(archived);
+            };
+            self.archive(claimed).await?;
+            archived += 1;
+        }
+    }
+
+    /// Stores either the archive or the failure, so the status endpoint can report it
+    async fn archive(&self, claimed: ClaimedExport) -> Result<(), Pool::Err> {
+        let archive = match self.build(claimed.user_id).await {
+            Err(ExportError::DataError(err)) => return Err(err),
+            archive => archive,
+        };
+
+        let mut tx = self.pool.begin_tx().await?;
+        match archive {
+            Ok(archive) => {
+                self.export_repo.complete(&mut tx, claimed.id, &archive).await?;
+                info!(user_id:display = claimed.user_id, export_id:display = claimed.id; "Exported personal data");
+            }
+            Err(err) => {
+                error!(export_id:display = claimed.id, err:err = err; "Failed to build export archive");
+                self.export_repo.fail(&mut tx, claimed.id).await?;
+            }
+        }
+        tx.commit().await
+    }
+
+    async fn build(&self, user_id: Uuid) -> Result<Vec<u8>, ExportError<Pool::Err>> {
+        let mut tx = self
+            .pool
+            .begin_tx()
+            .await
+            .map_err(ExportError::DataError)?;
+        let user = self
+            .user_repo
+            .find(&mut tx, user_id)
+            .await
+            .ok_or(ExportError::UserNotFound)?;
+        let account = self.idp_context.export(&mut tx, user_id).await?;
+        tx.commit()
+            .await
+            .map_err(ExportError::DataError)?;
+
+        tokio::task::spawn_blocking(move || export::build_archive(&user, &account))
+            .await
+            .map_err(|err| {
+                error!(user_id:display = user_id, err:err = err; "Export archiving interrupted");
+                ExportError::Failed
+            })?
+            .map_err(ExportError::ArchiveError)
+    }
+}
@@ -0,0 +1,51 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use log::{error, info};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, MissedTickBehavior};
+
+use crate::pool::{DatabasePool, TransactionOps};
+use crate::repo::export_repository::ExportRepository;
+
+/// Periodically deletes data exports past their `expires`, ready archives are the bulk of `data_exports`
+pub struct ExportCleanupJob<Pool, ExportRepo>
+where
+    Pool: DatabasePool + 'static,
+    ExportRepo: ExportRepository<Pool> + 'static,
+{
+    pub pool: Arc<Pool>,
+    pub export_repo: Arc<ExportRepo>,
+    pub interval: Duration,
+}
+
+impl<Pool, ExportRepo> ExportCleanupJob<Pool, ExportRepo>
+where
+    Pool: DatabasePool + 'static,
+    ExportRepo: ExportRepository<Pool> + 'static,
+{
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = interval(self.interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                ticker.tick().await;
+                match self.purge().await {
+                    Ok(0) => {}
+                    Ok(purged) => info!(purged = purged; "Deleted expired data exports"),
+                    Err(err) => error!(err:err = err; "Failed to delete expired data exports"),
+                }
+            }
+        })
+    }
+
+    async fn purge(&self) -> Result<u64, Pool::Err> {
+        let mut tx = self.pool.begin_tx().await?;
+        let deleted = self.export_repo.delete_expired(&mut tx, Utc::now()).await?;
+        tx.commit().await?;
+
+        Ok(deleted)
+    }
+}
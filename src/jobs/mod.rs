@@ -0,0 +1,4 @@
+pub(crate) mod account_purge;
+pub(crate) mod export_archive;
+pub(crate) mod export_cleanup;
+pub(crate) mod session_purge;
@@ -0,0 +1,126 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, MissedTickBehavior};
+
+use crate::pool::{DatabasePool, TransactionOps};
+use crate::repo::auth_repository::AuthRepository;
+use crate::repo::session_repository::SessionRepository;
+
+/// Periodically deletes expired sessions, refresh tokens, revoked token ids and stale login failure counters
+/// in bounded batches, so a single run never holds locks on a large part of a table
+pub struct SessionPurgeJob<Pool, SessionRepo, AuthRepo>
+where
+    Pool: DatabasePool + 'static,
+    SessionRepo: SessionRepository<Pool> + 'static,
+    AuthRepo: AuthRepository<Pool> + 'static,
+{
+    pub pool: Arc<Pool>,
+    pub session_repo: Arc<SessionRepo>,
+    pub auth_repo: Arc<AuthRepo>,
+    pub interval: Duration,
+    pub batch_size: i64,
+    /// Failure counters are kept while they count towards a lockout
+    pub lockout_window: chrono::Duration,
+}
+
+/// Tables cleaned up by [SessionPurgeJob]
+#[derive(Clone, Copy)]
+enum Purged {
+    Sessions,
+    RefreshTokens,
+    RevokedTokens,
+    LoginAttempts,
+}
+
+impl Purged {
+    const ALL: [Purged; 4] = [
+        Purged::Sessions,
+        Purged::RefreshTokens,
+        Purged::RevokedTokens,
+        Purged::LoginAttempts,
+    ];
+
+    fn table(self) -> &'static str {
+        match self {
+            Purged::Sessions => "sessions",
+            Purged::RefreshTokens => "refresh_tokens",
+            Purged::RevokedTokens => "revoked_tokens",
+            Purged::LoginAttempts => "login_attempts",
+        }
+    }
+}
+
+impl<Pool, SessionRepo, AuthRepo> SessionPurgeJob<Pool, SessionRepo, AuthRepo>
+where
+    Pool: DatabasePool + 'static,
+    SessionRepo: SessionRepository<Pool> + 'static,
+    AuthRepo: AuthRepository<Pool> + 'static,
+{
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = interval(self.interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                ticker.tick().await;
+                let before = Utc::now();
+                for purged in Purged::ALL {
+                    match self.purge(purged, before).await {
+                        Ok(0) => {}
+                        Ok(deleted) => info!(table = purged.table(), purged = deleted; "Purged expired rows"),
+                        Err(err) => error!(table = purged.table(), err:err = err; "Failed to purge expired rows"),
+                    }
+                }
+            }
+        })
+    }
+
+    async fn purge(&self, purged: Purged, before: DateTime<Utc>) -> Result<u64, Pool::Err> {
+        let mut total = 0;
+
+        loop {
+            let mut tx = self.pool.begin_tx().await?;
+            let deleted = self.delete_batch(&mut tx, purged, before).await?;
+            tx.commit().await?;
+
+            total += deleted;
+            if deleted < self.batch_size as u64 {
+                return Ok(total);
+            }
+        }
+    }
+
+    async fn delete_batch(
+        &self,
+        tx: &mut Pool::Tx,
+        purged: Purged,
+        before: DateTime<Utc>,
+    ) -> Result<u64, Pool::Err> {
+        match purged {
+            Purged::Sessions => {
+                self.session_repo
+                    .delete_expired(tx, before, self.batch_size)
+                    .await
+            }
+            Purged::RefreshTokens => {
+                self.session_repo
+                    .delete_expired_refresh_tokens(tx, before, self.batch_size)
+                    .await
+            }
+            Purged::RevokedTokens => {
+                self.session_repo
+                    .delete_expired_revocations(tx, before, self.batch_size)
+                    .await
+            }
+            Purged::LoginAttempts => {
+                self.auth_repo
+                    .delete_stale_failures(tx, before - self.lockout_window, before, self.batch_size)
+                    .await
+            }
+        }
+    }
+}
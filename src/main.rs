@@ -2,6 +2,7 @@ use std::collections::HashMap;
 use std::env;
 use std::net::Ipv4Addr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use confique::Config;
 use log::{error, info};
@@ -12,21 +13,35 @@ use sqlx::{PgPool};
 use structured_logger::async_json::new_writer;
 use structured_logger::Builder;
 use tap::TapFallible;
+use tokio::sync::Notify;
 use warp::Filter;
 
-use crate::auth::{AuthenticationFilter, PgIDPContext};
+use crate::auth::revocation_listener::RevocationListener;
+use crate::auth::cookies::SessionCookies;
+use crate::auth::{AuthenticationFilter, IDPContext, PgIDPContext};
 use crate::config::{ApplicationConfig, LoggerConfig, PgConfig};
+use crate::handlers::admin_handler::AdminHandler;
+use crate::handlers::export_handler::ExportHandler;
+use crate::handlers::metrics_handler::MetricsHandler;
 use crate::handlers::user_handler::UserHandler;
 use crate::handlers::RestHandler;
+use crate::jobs::account_purge::AccountPurgeJob;
+use crate::jobs::export_archive::ExportArchiveJob;
+use crate::jobs::export_cleanup::ExportCleanupJob;
+use crate::jobs::session_purge::SessionPurgeJob;
 use crate::repo::auth_repository::{PgAuthRepository};
+use crate::repo::export_repository::PgExportRepository;
 use crate::repo::session_repository::{PgSessionRepository};
 use crate::repo::user_repository::{PgUserRepository};
 
 mod auth;
 mod config;
 pub(crate) mod domain;
+mod export;
 mod extensions;
 mod handlers;
+mod jobs;
+mod notifier;
 pub(crate) mod pool;
 pub(crate) mod repo;
 
@@ -47,10 +62,12 @@ async fn main() {
         .file(config_file_path)
         .env()
         .load()
-        .expect(&format!(
-            "Failed to load application config from {}",
-            config_file_path
-        ));
+        .unwrap_or_else(|_| {
+            panic!(
+                "Failed to load application config from {}",
+                config_file_path
+            )
+        });
 
     initialize_logger(config.logger_config);
 
@@ -60,25 +77,116 @@ async fn main() {
     let session_repository = Arc::new(PgSessionRepository);
     let auth_repository = Arc::new(PgAuthRepository);
     let idp_context = Arc::new(PgIDPContext::new(
-        session_repository,
-        auth_repository,
+        session_repository.clone(),
+        auth_repository.clone(),
+        notifier::from_config(&config.notifier_config).expect("Invalid notifier configuration"),
         &config.auth_config,
-    ));
+    )
+    .expect("Invalid authentication configuration"));
+    idp_context
+        .resync(&pool)
+        .await
+        .expect("Failed to load revoked tokens");
     let auth_filter = Arc::new(AuthenticationFilter {
         pool: pool.clone(),
         idp: idp_context.clone(),
+        schemes: config.auth_config.authorization_schemes.clone(),
+        cookies: SessionCookies::new(&config.auth_config).expect("Invalid cookie configuration"),
+        trusted_proxies: config.auth_config.trusted_proxies.clone(),
     });
     let user_repository = Arc::new(PgUserRepository);
     let user_handler = Arc::new(UserHandler {
         pool: pool.clone(),
         authentication_filter: auth_filter.clone(),
         idp_context: idp_context.clone(),
-        repository: user_repository,
+        repository: user_repository.clone(),
+        account_deletion_grace_period: chrono::Duration::seconds(
+            config.auth_config.account_deletion_grace_period_seconds as i64,
+        ),
+    });
+
+    RevocationListener {
+        pool: pool.clone(),
+        idp: idp_context.clone(),
+        reconnect_delay: Duration::from_secs(
+            config.auth_config.revocation_reconnect_delay_seconds,
+        ),
+    }
+    .spawn();
+
+    SessionPurgeJob {
+        pool: pool.clone(),
+        session_repo: session_repository,
+        auth_repo: auth_repository,
+        interval: Duration::from_secs(config.auth_config.session_purge_interval_seconds),
+        batch_size: config.auth_config.session_purge_batch_size,
+        lockout_window: chrono::Duration::seconds(config.auth_config.lockout_window_seconds),
+    }
+    .spawn();
+
+    let export_repository = Arc::new(PgExportRepository);
+    let archive_requests = Arc::new(Notify::new());
+    let export_handler = Arc::new(ExportHandler {
+        pool: pool.clone(),
+        user_repo: user_repository.clone(),
+        export_repo: export_repository.clone(),
+        idp_context: idp_context.clone(),
+        authentication_filter: auth_filter.clone(),
+        sync_item_limit: config.export_config.sync_item_limit,
+        archive_lifetime: chrono::Duration::seconds(config.export_config.archive_lifetime_seconds as i64),
+        archive_requests: archive_requests.clone(),
+    });
+
+    ExportArchiveJob {
+        pool: pool.clone(),
+        user_repo: user_repository.clone(),
+        export_repo: export_repository.clone(),
+        idp_context: idp_context.clone(),
+        requests: archive_requests,
+        interval: Duration::from_secs(config.export_config.archive_interval_seconds),
+        claim_timeout: chrono::Duration::seconds(config.export_config.archive_claim_timeout_seconds as i64),
+        max_attempts: config.export_config.archive_max_attempts,
+    }
+    .spawn();
+
+    ExportCleanupJob {
+        pool: pool.clone(),
+        export_repo: export_repository,
+        interval: Duration::from_secs(config.export_config.cleanup_interval_seconds),
+    }
+    .spawn();
+
+    AccountPurgeJob {
+        pool: pool.clone(),
+        user_repo: user_repository,
+        interval: Duration::from_secs(config.auth_config.account_purge_interval_seconds),
+        grace_period: chrono::Duration::seconds(
+            config.auth_config.account_deletion_grace_period_seconds as i64,
+        ),
+        batch_size: config.auth_config.account_purge_batch_size,
+    }
+    .spawn();
+
+    let admin_handler = Arc::new(AdminHandler {
+        pool: pool.clone(),
+        idp_context: idp_context.clone(),
+        authentication_filter: auth_filter.clone(),
+    });
+
+    let metrics_handler = Arc::new(MetricsHandler {
+        idp_context: idp_context.clone(),
+        authentication_filter: auth_filter.clone(),
     });
 
+    let schemes = Arc::new(config.auth_config.authorization_schemes.clone());
     let routes = user_handler
         .routes()
-        .recover(handlers::rejection_handler::handle_rejections::<PgPool>);
+        .or(export_handler.routes())
+        .or(admin_handler.routes())
+        .or(metrics_handler.routes())
+        .recover(move |err| {
+            handlers::rejection_handler::handle_rejections::<PgPool>(schemes.clone(), err)
+        });
 
     warp::serve(routes).run((Ipv4Addr::UNSPECIFIED, 8080)).await;
 }
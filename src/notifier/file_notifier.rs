@@ -0,0 +1,45 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use log::info;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::notifier::{Notification, Notifier, NotifierError, Recipient};
+
+/// Drops every notification as a JSON file into `directory`, a stand-in for a mail server in local testing
+pub struct FileNotifier {
+    pub directory: PathBuf,
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    to: &'a Recipient,
+    subject: &'static str,
+    #[serde(flatten)]
+    notification: &'a Notification,
+}
+
+#[async_trait]
+impl Notifier for FileNotifier {
+    async fn notify(&self, recipient: &Recipient, notification: &Notification) -> Result<(), NotifierError> {
+        let payload = serde_json::to_vec_pretty(&Envelope {
+            to: recipient,
+            subject: notification.subject(),
+            notification,
+        })?;
+        let path = self.directory.join(format!(
+            "{}-{}-{}.json",
+            Utc::now().format("%Y%m%dT%H%M%S%.f"),
+            recipient.user_id,
+            Uuid::new_v4()
+        ));
+
+        tokio::fs::create_dir_all(&self.directory).await?;
+        tokio::fs::write(&path, payload).await?;
+        info!(user_id:display = recipient.user_id, path:debug = path; "Dropped notification file");
+
+        Ok(())
+    }
+}
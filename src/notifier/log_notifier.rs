@@ -0,0 +1,16 @@
+use async_trait::async_trait;
+use log::info;
+
+use crate::notifier::{Notification, Notifier, NotifierError, Recipient};
+
+/// Writes notifications to the application log, intended for local development only
+pub struct LogNotifier;
+
+#[async_trait]
+impl Notifier for LogNotifier {
+    async fn notify(&self, recipient: &Recipient, notification: &Notification) -> Result<(), NotifierError> {
+        let payload = serde_json::to_string(notification)?;
+        info!(user_id:display = recipient.user_id, email:debug = recipient.email, notification = payload; "Notification");
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::config::{NotifierConfig, NotifierKind};
+use crate::notifier::file_notifier::FileNotifier;
+use crate::notifier::log_notifier::LogNotifier;
+use crate::notifier::smtp_notifier::SmtpNotifier;
+
+pub(crate) mod file_notifier;
+pub(crate) mod log_notifier;
+pub(crate) mod smtp_notifier;
+
+#[derive(Serialize, Clone)]
+#[serde(tag = "type")]
+pub enum Notification {
+    PasswordReset {
+        login: String,
+        token: String,
+        expires: DateTime<Utc>,
+    },
+    EmailVerification {
+        login: String,
+        /// Absolute link to the verification endpoint, carries the token
+        link: String,
+        token: String,
+        expires: DateTime<Utc>,
+    },
+}
+
+impl Notification {
+    pub fn subject(&self) -> &'static str {
+        match self {
+            Notification::PasswordReset { .. } => "Password reset",
+            Notification::EmailVerification { .. } => "Confirm your email",
+        }
+    }
+
+    /// Plain text body for delivery channels read by humans
+    pub fn text(&self) -> String {
+        match self {
+            Notification::PasswordReset { login, token, expires } => format!(
+                "Hello, {login}!\n\nUse this token to reset your password: {token}\nIt expires at {expires}.\n\nIf you didn't request a password reset, ignore this message."
+            ),
+            Notification::EmailVerification { login, link, expires, .. } => format!(
+                "Hello, {login}!\n\nConfirm your email by opening {link}\nThe link expires at {expires}."
+            ),
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct Recipient {
+    pub user_id: Uuid,
+    /// Absent for accounts registered without email
+    pub email: Option<String>,
+}
+
+/// Delivers out-of-band messages (reset tokens and such) to users
+#[async_trait]
+pub trait Notifier
+where
+    Self: Send + Sync,
+{
+    async fn notify(&self, recipient: &Recipient, notification: &Notification) -> Result<(), NotifierError>;
+}
+
+#[derive(Error, Debug)]
+pub enum NotifierError {
+    #[error("Failed to serialize notification")]
+    Serialization(#[from] serde_json::Error),
+    #[error("Failed to write notification")]
+    Io(#[from] std::io::Error),
+    #[error("Recipient has no email address")]
+    NoAddress,
+    #[error("Invalid email address")]
+    Address(#[from] lettre::address::AddressError),
+    #[error("Failed to build email")]
+    Message(#[from] lettre::error::Error),
+    #[error("Failed to send email")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+}
+
+pub fn from_config(config: &NotifierConfig) -> Result<Arc<dyn Notifier>, NotifierError> {
+    Ok(match config.kind {
+        NotifierKind::Log => Arc::new(LogNotifier),
+        NotifierKind::File => Arc::new(FileNotifier {
+            directory: config.directory.clone().into(),
+        }),
+        NotifierKind::Smtp => Arc::new(SmtpNotifier::new(config)?),
+    })
+}
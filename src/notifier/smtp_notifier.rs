@@ -0,0 +1,52 @@
+use async_trait::async_trait;
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use log::info;
+
+use crate::config::NotifierConfig;
+use crate::notifier::{Notification, Notifier, NotifierError, Recipient};
+
+/// Sends notifications as plain text emails through an SMTP relay
+pub struct SmtpNotifier {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpNotifier {
+    pub fn new(config: &NotifierConfig) -> Result<Self, NotifierError> {
+        let mut transport = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.smtp_host)?
+            .port(config.smtp_port);
+        if let (Some(user), Some(password)) = (&config.smtp_user, &config.smtp_password) {
+            transport = transport.credentials(Credentials::new(user.clone(), password.clone()));
+        }
+
+        Ok(Self {
+            transport: transport.build(),
+            from: config.smtp_from.parse()?,
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for SmtpNotifier {
+    async fn notify(&self, recipient: &Recipient, notification: &Notification) -> Result<(), NotifierError> {
+        let to: Mailbox = recipient
+            .email
+            .as_deref()
+            .ok_or(NotifierError::NoAddress)?
+            .parse()?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(notification.subject())
+            .header(ContentType::TEXT_PLAIN)
+            .body(notification.text())?;
+
+        self.transport.send(message).await?;
+        info!(user_id:display = recipient.user_id; "Sent notification email");
+
+        Ok(())
+    }
+}
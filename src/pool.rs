@@ -23,6 +23,7 @@ where
 
 pub trait DbErrorOps {
     fn is_unique_violation(&self) -> bool;
+    fn is_foreign_key_violation(&self) -> bool;
 }
 
 #[async_trait]
@@ -44,19 +45,10 @@ impl<DB: sqlx::Database> TransactionOps<sqlx::Error> for sqlx::Transaction<'_, D
 
 impl DbErrorOps for sqlx::Error {
     fn is_unique_violation(&self) -> bool {
-        self.as_database_error().map_or(false, |err| err.is_unique_violation())
+        self.as_database_error().is_some_and(|err| err.is_unique_violation())
     }
-}
-
-#[cfg(test)]
-pub struct MockPool {}
-
-#[cfg(test)]
-impl DatabasePool for MockPool {
-    type Tx<'c> = ();
-    type Err = ();
 
-    async fn begin_tx(&self) -> Result<Self::Tx, Err> {
-        Ok(())
+    fn is_foreign_key_violation(&self) -> bool {
+        self.as_database_error().is_some_and(|err| err.is_foreign_key_violation())
     }
 }
@@ -1,6 +1,7 @@
 use crate::domain::user::{Credentials, User};
 use crate::extensions::Unit;
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use log::error;
 use sqlx::{Error, PgPool, Postgres, Transaction};
 use tap::TapFallible;
@@ -13,50 +14,707 @@ where
     Self: Send + Sync,
     Pool: DatabasePool,
 {
-    async fn find(&self, tx: &mut Pool::Tx, login: &str) -> Option<Credentials>;
+    async fn find(&self, tx: &mut Pool::Tx, login: &str) -> Option<StoredCredentials>;
+
+    async fn find_by_user(&self, tx: &mut Pool::Tx, user_id: Uuid) -> Option<StoredCredentials>;
 
     async fn save(
         &self,
         tx: &mut Pool::Tx,
         credentials: &Credentials,
+        email: Option<&str>,
+        state: AccountState,
         user: &User,
     ) -> Result<(), Pool::Err>;
+
+    /// Moves pending account to [AccountState::Active], returns `false` if the account wasn't pending
+    async fn activate(&self, tx: &mut Pool::Tx, user_id: Uuid) -> Result<bool, Pool::Err>;
+
+    async fn set_state(&self, tx: &mut Pool::Tx, user_id: Uuid, state: AccountState) -> Result<(), Pool::Err>;
+
+    /// Stores a new verification token, previously issued tokens of the user are discarded
+    async fn save_verification_token(
+        &self,
+        tx: &mut Pool::Tx,
+        token_hash: &str,
+        user_id: Uuid,
+        expires: DateTime<Utc>,
+    ) -> Result<(), Pool::Err>;
+
+    /// Deletes the verification token, so it can't be used twice
+    async fn consume_verification_token(
+        &self,
+        tx: &mut Pool::Tx,
+        token_hash: &str,
+    ) -> Result<Option<VerificationToken>, Pool::Err>;
+
+    async fn update_password(
+        &self,
+        tx: &mut Pool::Tx,
+        user_id: Uuid,
+        password: &str,
+    ) -> Result<(), Pool::Err>;
+
+    /// Stores a new reset token, previously issued tokens of the user are discarded
+    async fn save_reset_token(
+        &self,
+        tx: &mut Pool::Tx,
+        token_hash: &str,
+        user_id: Uuid,
+        expires: DateTime<Utc>,
+    ) -> Result<(), Pool::Err>;
+
+    /// Deletes the reset token, so it can't be used twice
+    async fn consume_reset_token(
+        &self,
+        tx: &mut Pool::Tx,
+        token_hash: &str,
+    ) -> Result<Option<ResetToken>, Pool::Err>;
+
+    /// Latest lock among `keys` which is still active at `now`
+    async fn locked_until(
+        &self,
+        tx: &mut Pool::Tx,
+        keys: &[String],
+        now: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>, Pool::Err>;
+
+    /// Counts a failed login, failures before `window_start` are forgotten. Returns current failure count
+    async fn record_failure(
+        &self,
+        tx: &mut Pool::Tx,
+        key: &str,
+        now: DateTime<Utc>,
+        window_start: DateTime<Utc>,
+    ) -> Result<i32, Pool::Err>;
+
+    async fn lock(&self, tx: &mut Pool::Tx, key: &str, until: DateTime<Utc>) -> Result<(), Pool::Err>;
+
+    async fn clear_failures(&self, tx: &mut Pool::Tx, key: &str) -> Result<(), Pool::Err>;
+
+    /// Deletes at most `limit` counters without failures since `window_start` and not locked at `now`,
+    /// returns amount of deleted counters
+    async fn delete_stale_failures(
+        &self,
+        tx: &mut Pool::Tx,
+        window_start: DateTime<Utc>,
+        now: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<u64, Pool::Err>;
+
+    /// Permissions granted by every role of the user
+    async fn find_permissions(&self, tx: &mut Pool::Tx, user_id: Uuid) -> Result<Vec<String>, Pool::Err>;
+
+    /// Replaces roles of the user
+    async fn set_roles(&self, tx: &mut Pool::Tx, user_id: Uuid, roles: &[String]) -> Result<(), Pool::Err>;
+
+    async fn find_totp(&self, tx: &mut Pool::Tx, user_id: Uuid) -> Result<Option<TotpCredential>, Pool::Err>;
+
+    /// Stores a new unconfirmed secret, confirmed secrets are never replaced
+    async fn save_totp(&self, tx: &mut Pool::Tx, user_id: Uuid, secret: &str) -> Result<(), Pool::Err>;
+
+    async fn confirm_totp(&self, tx: &mut Pool::Tx, user_id: Uuid) -> Result<(), Pool::Err>;
+
+    /// Marks the time step as used, returns `false` if it or a later step was used already,
+    /// so a code can't be replayed
+    async fn use_totp_step(&self, tx: &mut Pool::Tx, user_id: Uuid, step: i64) -> Result<bool, Pool::Err>;
+
+    /// Replaces recovery codes of the user
+    async fn save_recovery_codes(
+        &self,
+        tx: &mut Pool::Tx,
+        user_id: Uuid,
+        code_hashes: &[String],
+    ) -> Result<(), Pool::Err>;
+
+    /// Deletes the recovery code, so it can't be used twice. Returns `false` if there was no such code
+    async fn consume_recovery_code(
+        &self,
+        tx: &mut Pool::Tx,
+        user_id: Uuid,
+        code_hash: &str,
+    ) -> Result<bool, Pool::Err>;
+
+    /// Stores a new login challenge, previously issued challenges of the user are discarded
+    async fn save_login_challenge(
+        &self,
+        tx: &mut Pool::Tx,
+        challenge_hash: &str,
+        user_id: Uuid,
+        expires: DateTime<Utc>,
+    ) -> Result<(), Pool::Err>;
+
+    /// Deletes the challenge, so it can't be used twice
+    async fn consume_login_challenge(
+        &self,
+        tx: &mut Pool::Tx,
+        challenge_hash: &str,
+    ) -> Result<Option<LoginChallenge>, Pool::Err>;
+}
+
+pub struct StoredCredentials {
+    pub user_id: Uuid,
+    pub login: String,
+    pub password: String,
+    /// Absent for accounts registered before emails were collected
+    pub email: Option<String>,
+    pub state: AccountState,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AccountState {
+    /// Registered, but the email isn't verified yet
+    Pending,
+    Active,
+    Disabled,
+    /// Deleted by the user, awaits erasure after the grace period
+    Deleted,
+}
+
+impl AccountState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountState::Pending => "pending",
+            AccountState::Active => "active",
+            AccountState::Disabled => "disabled",
+            AccountState::Deleted => "deleted",
+        }
+    }
+}
+
+impl From<String> for AccountState {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "pending" => AccountState::Pending,
+            "active" => AccountState::Active,
+            "deleted" => AccountState::Deleted,
+            _ => AccountState::Disabled,
+        }
+    }
+}
+
+pub struct ResetToken {
+    pub user_id: Uuid,
+    pub expires: DateTime<Utc>,
+}
+
+pub struct VerificationToken {
+    pub user_id: Uuid,
+    pub expires: DateTime<Utc>,
+}
+
+pub struct TotpCredential {
+    /// Base32 encoded, kept in plain form as it's needed to compute the codes
+    pub secret: String,
+    pub confirmed: bool,
+}
+
+/// Pending login which passed password check and awaits the second factor
+pub struct LoginChallenge {
+    pub user_id: Uuid,
+    pub expires: DateTime<Utc>,
 }
 
 pub struct PgAuthRepository;
 
 #[async_trait]
 impl AuthRepository<PgPool> for PgAuthRepository {
-    async fn find(&self, tx: &mut Transaction<'static, Postgres>, login: &str) -> Option<Credentials> {
-        sqlx::query_as!(
-            Credentials,
-            "SELECT auth.login, auth.password FROM auth WHERE auth.login = $1",
+    async fn find(&self, tx: &mut Transaction<'static, Postgres>, login: &str) -> Option<StoredCredentials> {
+        sqlx::query!(
+            "SELECT auth.user_id, auth.login, auth.password, auth.email, auth.state FROM auth WHERE auth.login = $1",
             login
         )
         .fetch_one(&mut **tx)
         .await
         .ok()
+        .map(|row| StoredCredentials {
+            user_id: row.user_id,
+            login: row.login,
+            password: row.password,
+            email: row.email,
+            state: row.state.into(),
+        })
+    }
+
+    async fn find_by_user(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        user_id: Uuid,
+    ) -> Option<StoredCredentials> {
+        sqlx::query!(
+            "SELECT auth.user_id, auth.login, auth.password, auth.email, auth.state FROM auth WHERE auth.user_id = $1",
+            user_id
+        )
+        .fetch_one(&mut **tx)
+        .await
+        .ok()
+        .map(|row| StoredCredentials {
+            user_id: row.user_id,
+            login: row.login,
+            password: row.password,
+            email: row.email,
+            state: row.state.into(),
+        })
     }
 
     async fn save(
         &self,
         tx: &mut Transaction<'static, Postgres>,
         credentials: &Credentials,
+        email: Option<&str>,
+        state: AccountState,
         user: &User,
     ) -> Result<(), Error> {
         sqlx::query!(
             r#"
-            INSERT INTO auth(id, user_id, login, password)
-            VALUES ($1, $2, $3, $4)
+            INSERT INTO auth(id, user_id, login, password, email, state)
+            VALUES ($1, $2, $3, $4, $5, $6)
             "#,
             Uuid::new_v4(),
             &user.id,
             &credentials.login,
             &credentials.password,
+            email,
+            state.as_str(),
         )
         .execute(&mut **tx)
         .await
         .tap_err(|err| error!(err:err = *err; "Failed to save credentials"))
         .unit()
     }
+
+    async fn activate(&self, tx: &mut Transaction<'static, Postgres>, user_id: Uuid) -> Result<bool, Error> {
+        sqlx::query!(
+            "UPDATE auth SET state = $2 WHERE user_id = $1 AND state = $3",
+            user_id,
+            AccountState::Active.as_str(),
+            AccountState::Pending.as_str(),
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| error!(user_id:display = user_id, err:err = *err; "Failed to activate account"))
+        .map(|result| result.rows_affected() > 0)
+    }
+
+    async fn set_state(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        user_id: Uuid,
+        state: AccountState,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE auth SET state = $2 WHERE user_id = $1",
+            user_id,
+            state.as_str(),
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| error!(user_id:display = user_id, err:err = *err; "Failed to update account state"))
+        .unit()
+    }
+
+    async fn save_verification_token(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        token_hash: &str,
+        user_id: Uuid,
+        expires: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        sqlx::query!("DELETE FROM email_verification_tokens WHERE user_id = $1", user_id)
+            .execute(&mut **tx)
+            .await
+            .tap_err(|err| error!(user_id:display = user_id, err:err = *err; "Failed to discard verification tokens"))?;
+
+        sqlx::query!(
+            "INSERT INTO email_verification_tokens(token_hash, user_id, expires) VALUES ($1, $2, $3)",
+            token_hash,
+            user_id,
+            expires.naive_utc(),
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| error!(user_id:display = user_id, err:err = *err; "Failed to save verification token"))
+        .unit()
+    }
+
+    async fn consume_verification_token(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        token_hash: &str,
+    ) -> Result<Option<VerificationToken>, Error> {
+        sqlx::query!(
+            "DELETE FROM email_verification_tokens WHERE token_hash = $1 RETURNING user_id, expires",
+            token_hash
+        )
+        .fetch_optional(&mut **tx)
+        .await
+        .tap_err(|err| error!(err:err = *err; "Failed to consume verification token"))
+        .map(|row| {
+            row.map(|row| VerificationToken {
+                user_id: row.user_id,
+                expires: row.expires.and_utc(),
+            })
+        })
+    }
+
+    async fn update_password(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        user_id: Uuid,
+        password: &str,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE auth SET password = $2 WHERE user_id = $1",
+            user_id,
+            password,
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| error!(user_id:display = user_id, err:err = *err; "Failed to update password"))
+        .unit()
+    }
+
+    async fn save_reset_token(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        token_hash: &str,
+        user_id: Uuid,
+        expires: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        sqlx::query!("DELETE FROM password_reset_tokens WHERE user_id = $1", user_id)
+            .execute(&mut **tx)
+            .await
+            .tap_err(|err| error!(user_id:display = user_id, err:err = *err; "Failed to discard reset tokens"))?;
+
+        sqlx::query!(
+            "INSERT INTO password_reset_tokens(token_hash, user_id, expires) VALUES ($1, $2, $3)",
+            token_hash,
+            user_id,
+            expires.naive_utc(),
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| error!(user_id:display = user_id, err:err = *err; "Failed to save reset token"))
+        .unit()
+    }
+
+    async fn consume_reset_token(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        token_hash: &str,
+    ) -> Result<Option<ResetToken>, Error> {
+        sqlx::query!(
+            "DELETE FROM password_reset_tokens WHERE token_hash = $1 RETURNING user_id, expires",
+            token_hash
+        )
+        .fetch_optional(&mut **tx)
+        .await
+        .tap_err(|err| error!(err:err = *err; "Failed to consume reset token"))
+        .map(|row| {
+            row.map(|row| ResetToken {
+                user_id: row.user_id,
+                expires: row.expires.and_utc(),
+            })
+        })
+    }
+
+    async fn locked_until(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        keys: &[String],
+        now: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>, Error> {
+        sqlx::query_scalar!(
+            "SELECT MAX(locked_until) FROM login_attempts WHERE key = ANY($1) AND locked_until > $2",
+            keys,
+            now.naive_utc(),
+        )
+        .fetch_one(&mut **tx)
+        .await
+        .tap_err(|err| error!(err:err = *err; "Failed to check login lockout"))
+        .map(|locked_until| locked_until.map(|until| until.and_utc()))
+    }
+
+    async fn record_failure(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        key: &str,
+        now: DateTime<Utc>,
+        window_start: DateTime<Utc>,
+    ) -> Result<i32, Error> {
+        sqlx::query_scalar!(
+            r#"
+            INSERT INTO login_attempts(key, failures, last_failure)
+            VALUES ($1, 1, $2)
+            ON CONFLICT (key) DO UPDATE SET
+                failures = CASE
+                    WHEN login_attempts.last_failure < $3 THEN 1
+                    ELSE login_attempts.failures + 1
+                END,
+                last_failure = EXCLUDED.last_failure
+            RETURNING failures
+            "#,
+            key,
+            now.naive_utc(),
+            window_start.naive_utc(),
+        )
+        .fetch_one(&mut **tx)
+        .await
+        .tap_err(|err| error!(key = key, err:err = *err; "Failed to record login failure"))
+    }
+
+    async fn lock(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        key: &str,
+        until: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE login_attempts SET locked_until = $2 WHERE key = $1",
+            key,
+            until.naive_utc(),
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| error!(key = key, err:err = *err; "Failed to lock login"))
+        .unit()
+    }
+
+    async fn clear_failures(&self, tx: &mut Transaction<'static, Postgres>, key: &str) -> Result<(), Error> {
+        sqlx::query!("DELETE FROM login_attempts WHERE key = $1", key)
+            .execute(&mut **tx)
+            .await
+            .tap_err(|err| error!(key = key, err:err = *err; "Failed to clear login failures"))
+            .unit()
+    }
+
+    async fn delete_stale_failures(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        window_start: DateTime<Utc>,
+        now: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<u64, Error> {
+        sqlx::query!(
+            r#"
+            DELETE FROM login_attempts
+            WHERE key IN (
+                SELECT key FROM login_attempts
+                WHERE last_failure < $1 AND (locked_until IS NULL OR locked_until < $2)
+                LIMIT $3
+                FOR UPDATE SKIP LOCKED
+            )
+            "#,
+            window_start.naive_utc(),
+            now.naive_utc(),
+            limit,
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| error!(err:err = *err; "Failed to delete stale login failures"))
+        .map(|result| result.rows_affected())
+    }
+
+    async fn find_permissions(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        user_id: Uuid,
+    ) -> Result<Vec<String>, Error> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT DISTINCT role_permissions.permission
+            FROM user_roles
+            JOIN role_permissions ON role_permissions.role = user_roles.role
+            WHERE user_roles.user_id = $1
+            "#,
+            user_id
+        )
+        .fetch_all(&mut **tx)
+        .await
+        .tap_err(|err| error!(user_id:display = user_id, err:err = *err; "Failed to fetch permissions"))
+    }
+
+    async fn set_roles(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        user_id: Uuid,
+        roles: &[String],
+    ) -> Result<(), Error> {
+        sqlx::query!("DELETE FROM user_roles WHERE user_id = $1", user_id)
+            .execute(&mut **tx)
+            .await
+            .tap_err(|err| error!(user_id:display = user_id, err:err = *err; "Failed to discard roles"))?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO user_roles(user_id, role)
+            SELECT $1, role FROM UNNEST($2::varchar[]) AS role
+            "#,
+            user_id,
+            roles,
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| error!(user_id:display = user_id, err:err = *err; "Failed to save roles"))
+        .unit()
+    }
+
+    async fn find_totp(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        user_id: Uuid,
+    ) -> Result<Option<TotpCredential>, Error> {
+        sqlx::query_as!(
+            TotpCredential,
+            "SELECT secret, confirmed FROM totp_credentials WHERE user_id = $1",
+            user_id
+        )
+        .fetch_optional(&mut **tx)
+        .await
+        .tap_err(|err| error!(user_id:display = user_id, err:err = *err; "Failed to fetch TOTP credential"))
+    }
+
+    async fn save_totp(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        user_id: Uuid,
+        secret: &str,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO totp_credentials(user_id, secret, confirmed)
+            VALUES ($1, $2, false)
+            ON CONFLICT (user_id) DO UPDATE
+            SET secret = EXCLUDED.secret, last_used_step = NULL
+            WHERE NOT totp_credentials.confirmed
+            "#,
+            user_id,
+            secret,
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| error!(user_id:display = user_id, err:err = *err; "Failed to save TOTP credential"))
+        .unit()
+    }
+
+    async fn confirm_totp(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        user_id: Uuid,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE totp_credentials SET confirmed = true WHERE user_id = $1",
+            user_id
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| error!(user_id:display = user_id, err:err = *err; "Failed to confirm TOTP credential"))
+        .unit()
+    }
+
+    async fn use_totp_step(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        user_id: Uuid,
+        step: i64,
+    ) -> Result<bool, Error> {
+        sqlx::query!(
+            r#"
+            UPDATE totp_credentials SET last_used_step = $2
+            WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)
+            "#,
+            user_id,
+            step,
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| error!(user_id:display = user_id, err:err = *err; "Failed to record TOTP step"))
+        .map(|result| result.rows_affected() > 0)
+    }
+
+    async fn save_recovery_codes(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        user_id: Uuid,
+        code_hashes: &[String],
+    ) -> Result<(), Error> {
+        sqlx::query!("DELETE FROM totp_recovery_codes WHERE user_id = $1", user_id)
+            .execute(&mut **tx)
+            .await
+            .tap_err(|err| error!(user_id:display = user_id, err:err = *err; "Failed to discard recovery codes"))?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO totp_recovery_codes(code_hash, user_id)
+            SELECT code_hash, $2 FROM UNNEST($1::varchar[]) AS code_hash
+            "#,
+            code_hashes,
+            user_id,
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| error!(user_id:display = user_id, err:err = *err; "Failed to save recovery codes"))
+        .unit()
+    }
+
+    async fn consume_recovery_code(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        user_id: Uuid,
+        code_hash: &str,
+    ) -> Result<bool, Error> {
+        sqlx::query!(
+            "DELETE FROM totp_recovery_codes WHERE user_id = $1 AND code_hash = $2",
+            user_id,
+            code_hash,
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| error!(user_id:display = user_id, err:err = *err; "Failed to consume recovery code"))
+        .map(|result| result.rows_affected() > 0)
+    }
+
+    async fn save_login_challenge(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        challenge_hash: &str,
+        user_id: Uuid,
+        expires: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        sqlx::query!("DELETE FROM login_challenges WHERE user_id = $1", user_id)
+            .execute(&mut **tx)
+            .await
+            .tap_err(|err| error!(user_id:display = user_id, err:err = *err; "Failed to discard login challenges"))?;
+
+        sqlx::query!(
+            "INSERT INTO login_challenges(challenge_hash, user_id, expires) VALUES ($1, $2, $3)",
+            challenge_hash,
+            user_id,
+            expires.naive_utc(),
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| error!(user_id:display = user_id, err:err = *err; "Failed to save login challenge"))
+        .unit()
+    }
+
+    async fn consume_login_challenge(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        challenge_hash: &str,
+    ) -> Result<Option<LoginChallenge>, Error> {
+        sqlx::query!(
+            "DELETE FROM login_challenges WHERE challenge_hash = $1 RETURNING user_id, expires",
+            challenge_hash
+        )
+        .fetch_optional(&mut **tx)
+        .await
+        .tap_err(|err| error!(err:err = *err; "Failed to consume login challenge"))
+        .map(|row| {
+            row.map(|row| LoginChallenge {
+                user_id: row.user_id,
+                expires: row.expires.and_utc(),
+            })
+        })
+    }
 }
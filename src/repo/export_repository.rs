@@ -0,0 +1,240 @@
+use crate::extensions::Unit;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::error;
+use sqlx::{Error, PgPool, Postgres, Transaction};
+use tap::TapFallible;
+use uuid::Uuid;
+use crate::pool::DatabasePool;
+
+#[async_trait]
+pub trait ExportRepository<Pool>
+where
+    Self: Send + Sync,
+    Pool: DatabasePool,
+{
+    /// Registers a pending export, previous exports of the user are discarded
+    async fn create(
+        &self,
+        tx: &mut Pool::Tx,
+        id: Uuid,
+        user_id: Uuid,
+        expires: DateTime<Utc>,
+    ) -> Result<(), Pool::Err>;
+
+    /// Not expired export of the user which is still being archived
+    async fn find_pending(
+        &self,
+        tx: &mut Pool::Tx,
+        user_id: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Uuid>, Pool::Err>;
+
+    /// Takes the oldest pending export nobody works on until `claimed_until`.
+    /// Claims of crashed instances expire, so their exports are resumed by another worker
+    async fn claim(
+        &self,
+        tx: &mut Pool::Tx,
+        now: DateTime<Utc>,
+        claimed_until: DateTime<Utc>,
+        max_attempts: i32,
+    ) -> Result<Option<ClaimedExport>, Pool::Err>;
+
+    /// Marks failed pending exports whose last claim expired after `max_attempts` attempts
+    async fn fail_exhausted(
+        &self,
+        tx: &mut Pool::Tx,
+        now: DateTime<Utc>,
+        max_attempts: i32,
+    ) -> Result<u64, Pool::Err>;
+
+    async fn complete(&self, tx: &mut Pool::Tx, id: Uuid, archive: &[u8]) -> Result<(), Pool::Err>;
+
+    async fn fail(&self, tx: &mut Pool::Tx, id: Uuid) -> Result<(), Pool::Err>;
+
+    /// Not expired export of the user, exports of other users are never returned
+    async fn find(
+        &self,
+        tx: &mut Pool::Tx,
+        id: Uuid,
+        user_id: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<Option<StoredExport>, Pool::Err>;
+
+    async fn delete_expired(&self, tx: &mut Pool::Tx, before: DateTime<Utc>) -> Result<u64, Pool::Err>;
+}
+
+pub struct ClaimedExport {
+    pub id: Uuid,
+    pub user_id: Uuid,
+}
+
+pub enum StoredExport {
+    Pending,
+    Ready(Vec<u8>),
+    Failed,
+}
+
+const PENDING: &str = "pending";
+const READY: &str = "ready";
+const FAILED: &str = "failed";
+
+pub struct PgExportRepository;
+
+#[async_trait]
+impl ExportRepository<PgPool> for PgExportRepository {
+    async fn create(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        id: Uuid,
+        user_id: Uuid,
+        expires: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        sqlx::query!("DELETE FROM data_exports WHERE user_id = $1", user_id)
+            .execute(&mut **tx)
+            .await
+            .tap_err(|err| error!(user_id:display = user_id, err:err = *err; "Failed to discard exports"))?;
+
+        sqlx::query!(
+            "INSERT INTO data_exports(id, user_id, status, expires) VALUES ($1, $2, $3, $4)",
+            id,
+            user_id,
+            PENDING,
+            expires.naive_utc(),
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| error!(user_id:display = user_id, err:err = *err; "Failed to save export"))
+        .unit()
+    }
+
+    async fn find_pending(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        user_id: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Uuid>, Error> {
+        sqlx::query_scalar!(
+            "SELECT id FROM data_exports WHERE user_id = $1 AND status = $2 AND expires > $3",
+            user_id,
+            PENDING,
+            now.naive_utc(),
+        )
+        .fetch_optional(&mut **tx)
+        .await
+        .tap_err(|err| error!(user_id:display = user_id, err:err = *err; "Failed to fetch pending export"))
+    }
+
+    async fn claim(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        now: DateTime<Utc>,
+        claimed_until: DateTime<Utc>,
+        max_attempts: i32,
+    ) -> Result<Option<ClaimedExport>, Error> {
+        sqlx::query!(
+            r#"
+            UPDATE data_exports SET claimed_until = $2, attempts = attempts + 1
+            WHERE id = (
+                SELECT id FROM data_exports
+                WHERE status = $3 AND expires > $1 AND attempts < $4
+                    AND (claimed_until IS NULL OR claimed_until <= $1)
+                ORDER BY claimed_until NULLS FIRST
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, user_id
+            "#,
+            now.naive_utc(),
+            claimed_until.naive_utc(),
+            PENDING,
+            max_attempts,
+        )
+        .fetch_optional(&mut **tx)
+        .await
+        .tap_err(|err| error!(err:err = *err; "Failed to claim pending export"))
+        .map(|row| row.map(|row| ClaimedExport { id: row.id, user_id: row.user_id }))
+    }
+
+    async fn fail_exhausted(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        now: DateTime<Utc>,
+        max_attempts: i32,
+    ) -> Result<u64, Error> {
+        sqlx::query!(
+            "UPDATE data_exports SET status = $2 WHERE status = $3 AND attempts >= $4 AND claimed_until <= $1",
+            now.naive_utc(),
+            FAILED,
+            PENDING,
+            max_attempts,
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| error!(err:err = *err; "Failed to fail exhausted exports"))
+        .map(|result| result.rows_affected())
+    }
+
+    async fn complete(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        id: Uuid,
+        archive: &[u8],
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE data_exports SET status = $2, archive = $3 WHERE id = $1",
+            id,
+            READY,
+            archive,
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| error!(id:display = id, err:err = *err; "Failed to save export archive"))
+        .unit()
+    }
+
+    async fn fail(&self, tx: &mut Transaction<'static, Postgres>, id: Uuid) -> Result<(), Error> {
+        sqlx::query!("UPDATE data_exports SET status = $2 WHERE id = $1", id, FAILED)
+            .execute(&mut **tx)
+            .await
+            .tap_err(|err| error!(id:display = id, err:err = *err; "Failed to mark export failed"))
+            .unit()
+    }
+
+    async fn find(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        id: Uuid,
+        user_id: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<Option<StoredExport>, Error> {
+        sqlx::query!(
+            "SELECT status, archive FROM data_exports WHERE id = $1 AND user_id = $2 AND expires > $3",
+            id,
+            user_id,
+            now.naive_utc(),
+        )
+        .fetch_optional(&mut **tx)
+        .await
+        .tap_err(|err| error!(id:display = id, err:err = *err; "Failed to fetch export"))
+        .map(|row| {
+            row.map(|row| match (row.status.as_str(), row.archive) {
+                (READY, Some(archive)) => StoredExport::Ready(archive),
+                (PENDING, _) => StoredExport::Pending,
+                _ => StoredExport::Failed,
+            })
+        })
+    }
+
+    async fn delete_expired(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, Error> {
+        sqlx::query!("DELETE FROM data_exports WHERE expires <= $1", before.naive_utc())
+            .execute(&mut **tx)
+            .await
+            .tap_err(|err| error!(err:err = *err; "Failed to delete expired exports"))
+            .map(|result| result.rows_affected())
+    }
+}
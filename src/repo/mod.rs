@@ -1,3 +1,4 @@
 pub(crate) mod auth_repository;
+pub(crate) mod export_repository;
 pub(crate) mod session_repository;
 pub(crate) mod user_repository;
@@ -5,51 +5,214 @@ use chrono::{DateTime, Utc};
 use log::warn;
 use sqlx::{Error, PgPool, Postgres, Transaction};
 use tap::TapFallible;
+use uuid::Uuid;
 use crate::pool::DatabasePool;
 
+/// Postgres `NOTIFY` channel, payload is the hashed id of the revoked session
+pub const SESSION_REVOCATIONS_CHANNEL: &str = "session_revocations";
+
+pub struct RefreshToken {
+    pub token_hash: String,
+    /// Every token obtained by rotating the same login shares the family
+    pub family_id: Uuid,
+    pub user_id: Uuid,
+    pub expires: DateTime<Utc>,
+    pub used: bool,
+}
+
 #[async_trait]
 pub trait SessionRepository<Pool>
 where
     Self: Send + Sync,
     Pool: DatabasePool,
 {
-    async fn not_expired(
+    async fn find(&self, tx: &mut Pool::Tx, session_id: &str) -> Option<Session>;
+
+    /// Not expired sessions of the user, most recently used first
+    async fn find_all_of_user(
         &self,
         tx: &mut Pool::Tx,
-        session_id: &str,
-    ) -> Option<DateTime<Utc>>;
+        user_id: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Session>, Pool::Err>;
 
     async fn save(&self, tx: &mut Pool::Tx, session: &Session) -> Result<(), Pool::Err>;
+
+    /// Records activity and moves expiration forward to `expires` if present,
+    /// returns `false` if the session doesn't exist anymore
+    async fn touch(
+        &self,
+        tx: &mut Pool::Tx,
+        session_id: &str,
+        last_seen: DateTime<Utc>,
+        expires: Option<DateTime<Utc>>,
+    ) -> Result<bool, Pool::Err>;
+
+    async fn delete(&self, tx: &mut Pool::Tx, session_id: &str) -> Result<bool, Pool::Err>;
+
+    /// Deletes at most `limit` sessions expired before `before`, returns amount of deleted sessions
+    async fn delete_expired(
+        &self,
+        tx: &mut Pool::Tx,
+        before: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<u64, Pool::Err>;
+
+    /// Publishes revocations to other instances, delivered once the transaction commits
+    async fn notify_revoked(&self, tx: &mut Pool::Tx, session_ids: &[String]) -> Result<(), Pool::Err>;
+
+    /// Keeps ids of revoked JWTs until `expires`, since the tokens stay verifiable until then
+    async fn save_revoked_tokens(
+        &self,
+        tx: &mut Pool::Tx,
+        jtis: &[String],
+        expires: DateTime<Utc>,
+    ) -> Result<(), Pool::Err>;
+
+    async fn find_revoked_tokens(
+        &self,
+        tx: &mut Pool::Tx,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<(String, DateTime<Utc>)>, Pool::Err>;
+
+    /// Deletes at most `limit` revoked token ids which are expired at `before`, returns amount of deleted ids
+    async fn delete_expired_revocations(
+        &self,
+        tx: &mut Pool::Tx,
+        before: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<u64, Pool::Err>;
+
+    /// Deletes every session of the user except `keep`, returns ids of deleted sessions
+    async fn delete_all_of_user(
+        &self,
+        tx: &mut Pool::Tx,
+        user_id: Uuid,
+        keep: Option<&str>,
+    ) -> Result<Vec<String>, Pool::Err>;
+
+    async fn save_refresh_token(&self, tx: &mut Pool::Tx, token: &RefreshToken) -> Result<(), Pool::Err>;
+
+    /// Locks the token row, so concurrent rotations of the same token are serialized
+    async fn find_refresh_token(
+        &self,
+        tx: &mut Pool::Tx,
+        token_hash: &str,
+    ) -> Result<Option<RefreshToken>, Pool::Err>;
+
+    async fn mark_refresh_token_used(&self, tx: &mut Pool::Tx, token_hash: &str) -> Result<(), Pool::Err>;
+
+    /// Deletes refresh tokens and sessions of the family, returns ids of deleted sessions
+    async fn delete_family(&self, tx: &mut Pool::Tx, family_id: Uuid) -> Result<Vec<String>, Pool::Err>;
+
+    async fn delete_refresh_tokens_of_session(
+        &self,
+        tx: &mut Pool::Tx,
+        session_id: &str,
+    ) -> Result<(), Pool::Err>;
+
+    /// Deletes every refresh token of the user except ones of the `keep` session family
+    async fn delete_refresh_tokens_of_user(
+        &self,
+        tx: &mut Pool::Tx,
+        user_id: Uuid,
+        keep: Option<&str>,
+    ) -> Result<(), Pool::Err>;
+
+    /// Deletes at most `limit` refresh tokens expired before `before`, returns amount of deleted tokens
+    async fn delete_expired_refresh_tokens(
+        &self,
+        tx: &mut Pool::Tx,
+        before: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<u64, Pool::Err>;
 }
 
 pub struct PgSessionRepository;
 
 #[async_trait]
 impl SessionRepository<PgPool> for PgSessionRepository {
-    async fn not_expired(
+    async fn find(
         &self,
         tx: &mut Transaction<'static, Postgres>,
         session_id: &str,
-    ) -> Option<DateTime<Utc>> {
-        sqlx::query_scalar!(
-            r#"SELECT expires as "expires!" FROM sessions WHERE session_id = $1"#,
+    ) -> Option<Session> {
+        sqlx::query!(
+            r#"
+            SELECT session_id, user_id, family_id, created_at, expires, last_seen, user_agent, client_ip
+            FROM sessions
+            WHERE session_id = $1
+            "#,
             &session_id
         )
         .fetch_one(&mut **tx)
         .await
-        .map(|expires| expires.and_utc())
+        .map(|row| Session {
+            session_id: row.session_id,
+            user_id: row.user_id,
+            family_id: row.family_id,
+            created: row.created_at.and_utc(),
+            expires: row.expires.and_utc(),
+            last_seen: row.last_seen.and_utc(),
+            user_agent: row.user_agent,
+            client_ip: row.client_ip,
+        })
         .ok()
     }
 
+    async fn find_all_of_user(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        user_id: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Session>, Error> {
+        sqlx::query!(
+            r#"
+            SELECT session_id, user_id, family_id, created_at, expires, last_seen, user_agent, client_ip
+            FROM sessions
+            WHERE user_id = $1 AND expires > $2
+            ORDER BY last_seen DESC
+            "#,
+            user_id,
+            now.naive_utc(),
+        )
+        .fetch_all(&mut **tx)
+        .await
+        .tap_err(|err| warn!(user_id:display = user_id, err:err = *err; "Failed to fetch sessions of user"))
+        .map(|rows| {
+            rows.into_iter()
+                .map(|row| Session {
+                    session_id: row.session_id,
+                    user_id: row.user_id,
+                    family_id: row.family_id,
+                    created: row.created_at.and_utc(),
+                    expires: row.expires.and_utc(),
+                    last_seen: row.last_seen.and_utc(),
+                    user_agent: row.user_agent,
+                    client_ip: row.client_ip,
+                })
+                .collect()
+        })
+    }
+
     async fn save(
         &self,
         tx: &mut Transaction<'static, Postgres>,
         session: &Session,
     ) -> Result<(), Error> {
         sqlx::query!(
-            "INSERT INTO sessions(session_id, expires) VALUES ($1, $2)",
+            r#"
+            INSERT INTO sessions(session_id, user_id, family_id, created_at, expires, last_seen, user_agent, client_ip)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#,
             session.session_id.clone(),
+            session.user_id,
+            session.family_id,
+            session.created.naive_utc(),
             session.expires.clone().naive_utc(),
+            session.last_seen.naive_utc(),
+            session.user_agent,
+            session.client_ip,
         )
         .execute(&mut **tx)
         .await
@@ -58,4 +221,315 @@ impl SessionRepository<PgPool> for PgSessionRepository {
         )
         .unit()
     }
+
+    async fn touch(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        session_id: &str,
+        last_seen: DateTime<Utc>,
+        expires: Option<DateTime<Utc>>,
+    ) -> Result<bool, Error> {
+        sqlx::query!(
+            r#"
+            UPDATE sessions
+            SET last_seen = GREATEST(last_seen, $2), expires = GREATEST(expires, COALESCE($3, expires))
+            WHERE session_id = $1
+            "#,
+            session_id,
+            last_seen.naive_utc(),
+            expires.map(|expires| expires.naive_utc()),
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| warn!(session_id = session_id, err:err = *err; "Failed to touch session"))
+        .map(|result| result.rows_affected() > 0)
+    }
+
+    async fn delete(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        session_id: &str,
+    ) -> Result<bool, Error> {
+        sqlx::query!("DELETE FROM sessions WHERE session_id = $1", session_id)
+            .execute(&mut **tx)
+            .await
+            .tap_err(|err| warn!(session_id = session_id, err:err = *err; "Failed to delete session"))
+            .map(|result| result.rows_affected() > 0)
+    }
+
+    async fn delete_expired(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        before: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<u64, Error> {
+        sqlx::query!(
+            r#"
+            DELETE FROM sessions
+            WHERE session_id IN (
+                SELECT session_id FROM sessions
+                WHERE expires < $1
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            )
+            "#,
+            before.naive_utc(),
+            limit,
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| warn!(err:err = *err; "Failed to delete expired sessions"))
+        .map(|result| result.rows_affected())
+    }
+
+    async fn notify_revoked(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        session_ids: &[String],
+    ) -> Result<(), Error> {
+        if session_ids.is_empty() {
+            return Ok(());
+        }
+
+        sqlx::query("SELECT pg_notify($1, session_id) FROM UNNEST($2::varchar[]) AS session_id")
+            .bind(SESSION_REVOCATIONS_CHANNEL)
+            .bind(session_ids)
+            .execute(&mut **tx)
+            .await
+            .tap_err(|err| warn!(err:err = *err; "Failed to notify about revoked sessions"))
+            .unit()
+    }
+
+    async fn save_revoked_tokens(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        jtis: &[String],
+        expires: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO revoked_tokens(jti, expires)
+            SELECT jti, $2 FROM UNNEST($1::varchar[]) AS jti
+            ON CONFLICT (jti) DO NOTHING
+            "#,
+            jtis,
+            expires.naive_utc(),
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| warn!(err:err = *err; "Failed to save revoked tokens"))
+        .unit()
+    }
+
+    async fn find_revoked_tokens(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<(String, DateTime<Utc>)>, Error> {
+        sqlx::query!(
+            "SELECT jti, expires FROM revoked_tokens WHERE expires > $1",
+            now.naive_utc(),
+        )
+        .fetch_all(&mut **tx)
+        .await
+        .tap_err(|err| warn!(err:err = *err; "Failed to fetch revoked tokens"))
+        .map(|rows| {
+            rows.into_iter()
+                .map(|row| (row.jti, row.expires.and_utc()))
+                .collect()
+        })
+    }
+
+    async fn delete_expired_revocations(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        before: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<u64, Error> {
+        sqlx::query!(
+            r#"
+            DELETE FROM revoked_tokens
+            WHERE jti IN (
+                SELECT jti FROM revoked_tokens
+                WHERE expires < $1
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            )
+            "#,
+            before.naive_utc(),
+            limit,
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| warn!(err:err = *err; "Failed to delete expired revoked tokens"))
+        .map(|result| result.rows_affected())
+    }
+
+    async fn delete_all_of_user(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        user_id: Uuid,
+        keep: Option<&str>,
+    ) -> Result<Vec<String>, Error> {
+        sqlx::query_scalar!(
+            r#"
+            DELETE FROM sessions
+            WHERE user_id = $1 AND session_id IS DISTINCT FROM $2
+            RETURNING session_id
+            "#,
+            user_id,
+            keep,
+        )
+        .fetch_all(&mut **tx)
+        .await
+        .tap_err(
+            |err| warn!(user_id:display = user_id, err:err = *err; "Failed to delete sessions of user"),
+        )
+    }
+
+    async fn save_refresh_token(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        token: &RefreshToken,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO refresh_tokens(token_hash, family_id, user_id, expires, used)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            token.token_hash,
+            token.family_id,
+            token.user_id,
+            token.expires.naive_utc(),
+            token.used,
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| warn!(family_id:display = token.family_id, err:err = *err; "Failed to save refresh token"))
+        .unit()
+    }
+
+    async fn find_refresh_token(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        token_hash: &str,
+    ) -> Result<Option<RefreshToken>, Error> {
+        sqlx::query!(
+            r#"
+            SELECT token_hash, family_id, user_id, expires, used
+            FROM refresh_tokens
+            WHERE token_hash = $1
+            FOR UPDATE
+            "#,
+            token_hash
+        )
+        .fetch_optional(&mut **tx)
+        .await
+        .tap_err(|err| warn!(err:err = *err; "Failed to fetch refresh token"))
+        .map(|row| {
+            row.map(|row| RefreshToken {
+                token_hash: row.token_hash,
+                family_id: row.family_id,
+                user_id: row.user_id,
+                expires: row.expires.and_utc(),
+                used: row.used,
+            })
+        })
+    }
+
+    async fn mark_refresh_token_used(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        token_hash: &str,
+    ) -> Result<(), Error> {
+        sqlx::query!("UPDATE refresh_tokens SET used = true WHERE token_hash = $1", token_hash)
+            .execute(&mut **tx)
+            .await
+            .tap_err(|err| warn!(err:err = *err; "Failed to mark refresh token used"))
+            .unit()
+    }
+
+    async fn delete_family(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        family_id: Uuid,
+    ) -> Result<Vec<String>, Error> {
+        sqlx::query!("DELETE FROM refresh_tokens WHERE family_id = $1", family_id)
+            .execute(&mut **tx)
+            .await
+            .tap_err(|err| warn!(family_id:display = family_id, err:err = *err; "Failed to delete refresh token family"))?;
+
+        sqlx::query_scalar!(
+            "DELETE FROM sessions WHERE family_id = $1 RETURNING session_id",
+            family_id
+        )
+        .fetch_all(&mut **tx)
+        .await
+        .tap_err(|err| warn!(family_id:display = family_id, err:err = *err; "Failed to delete sessions of family"))
+    }
+
+    async fn delete_refresh_tokens_of_session(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        session_id: &str,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            r#"
+            DELETE FROM refresh_tokens
+            WHERE family_id = (SELECT family_id FROM sessions WHERE session_id = $1)
+            "#,
+            session_id
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| warn!(session_id = session_id, err:err = *err; "Failed to delete refresh tokens of session"))
+        .unit()
+    }
+
+    async fn delete_refresh_tokens_of_user(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        user_id: Uuid,
+        keep: Option<&str>,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            r#"
+            DELETE FROM refresh_tokens
+            WHERE user_id = $1 AND family_id IS DISTINCT FROM (
+                SELECT family_id FROM sessions WHERE session_id = $2
+            )
+            "#,
+            user_id,
+            keep,
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| warn!(user_id:display = user_id, err:err = *err; "Failed to delete refresh tokens of user"))
+        .unit()
+    }
+
+    async fn delete_expired_refresh_tokens(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        before: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<u64, Error> {
+        sqlx::query!(
+            r#"
+            DELETE FROM refresh_tokens
+            WHERE token_hash IN (
+                SELECT token_hash FROM refresh_tokens
+                WHERE expires < $1
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            )
+            "#,
+            before.naive_utc(),
+            limit,
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| warn!(err:err = *err; "Failed to delete expired refresh tokens"))
+        .map(|result| result.rows_affected())
+    }
 }
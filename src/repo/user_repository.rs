@@ -1,9 +1,11 @@
-use crate::domain::user::{Interest, User};
+use crate::domain::user::{Interest, User, UserPatch};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use log::warn;
 use sqlx::{Error, PgPool, Postgres, Transaction};
 use tap::TapFallible;
 use uuid::Uuid;
+use crate::extensions::Unit;
 use crate::pool::DatabasePool;
 
 #[async_trait]
@@ -14,7 +16,38 @@ where
 {
     async fn find(&self, tx: &mut Pool::Tx, id: Uuid) -> Option<User>;
 
+    async fn search(
+        &self,
+        tx: &mut Pool::Tx,
+        first_name_prefix: &str,
+        last_name_prefix: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<User>, Pool::Err>;
+
     async fn save(&self, tx: &mut Pool::Tx, user: User) -> Result<(), Pool::Err>;
+
+    /// Applies the patch and increments the version, provided the current version is one of `expected_versions`
+    /// (any version when `None`). Returns the new version, or `None` if the user is missing or the version differs
+    async fn update(
+        &self,
+        tx: &mut Pool::Tx,
+        id: Uuid,
+        expected_versions: Option<&[i64]>,
+        patch: UserPatch,
+    ) -> Result<Option<i64>, Pool::Err>;
+
+    /// Hides the user from lookups, the data is kept until [UserRepository::purge_deleted]
+    async fn mark_deleted(&self, tx: &mut Pool::Tx, id: Uuid, deleted_at: DateTime<Utc>) -> Result<(), Pool::Err>;
+
+    /// Erases at most `limit` users deleted before `before` together with every row referencing them.
+    /// Returns amount of erased users
+    async fn purge_deleted(
+        &self,
+        tx: &mut Pool::Tx,
+        before: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<u64, Pool::Err>;
 }
 
 #[derive(Clone)]
@@ -33,17 +66,19 @@ impl UserRepository<PgPool> for PgUserRepository {
                 users.birth_date,
                 users.gender,
                 users.city,
-                COALESCE(NULLIF(ARRAY_AGG((interest.name, interest.description)), '{NULL}'), '{}') AS "interests!: Vec<Interest>"
+                users.version,
+                COALESCE(ARRAY_AGG((interest.name, interest.description)) FILTER (WHERE interest.user_id IS NOT NULL), '{}') AS "interests!: Vec<Interest>"
             FROM users
             LEFT JOIN interest ON interest.user_id = users.id
-            WHERE users.id = $1
+            WHERE users.id = $1 AND users.deleted_at IS NULL
             GROUP BY
                 users.id,
                 users.first_name,
                 users.last_name,
                 users.birth_date,
                 users.gender,
-                users.city
+                users.city,
+                users.version
             "#,
             &id
         )
@@ -53,6 +88,58 @@ impl UserRepository<PgPool> for PgUserRepository {
             .ok()
     }
 
+    async fn search(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        first_name_prefix: &str,
+        last_name_prefix: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<User>, Error> {
+        sqlx::query_as!(
+            User,
+            r#"
+            SELECT
+                users.id AS "id!",
+                users.first_name AS "first_name!",
+                users.last_name AS "last_name!",
+                users.birth_date AS "birth_date!",
+                users.gender AS "gender!",
+                users.city AS "city!",
+                users.version AS "version!",
+                COALESCE(ARRAY_AGG((interest.name, interest.description)) FILTER (WHERE interest.user_id IS NOT NULL), '{}') AS "interests!: Vec<Interest>"
+            FROM (
+                SELECT * FROM users
+                WHERE users.first_name LIKE $1 AND users.last_name LIKE $2 AND users.deleted_at IS NULL
+                ORDER BY users.id
+                LIMIT $3 OFFSET $4
+            ) AS users
+            LEFT JOIN interest ON interest.user_id = users.id
+            GROUP BY
+                users.id,
+                users.first_name,
+                users.last_name,
+                users.birth_date,
+                users.gender,
+                users.city,
+                users.version
+            ORDER BY users.id
+            "#,
+            like_prefix(first_name_prefix),
+            like_prefix(last_name_prefix),
+            limit,
+            offset,
+        )
+            .fetch_all(&mut **tx)
+            .await
+            .tap_err(|err| warn!(
+                first_name = first_name_prefix,
+                last_name = last_name_prefix,
+                err:err = *err;
+                "Failed to search users"
+            ))
+    }
+
     async fn save(&self, tx: &mut Transaction<'static, Postgres>, user: User) -> Result<(), Error> {
         let _ = sqlx::query!(
             r#"
@@ -83,26 +170,179 @@ impl UserRepository<PgPool> for PgUserRepository {
         .await
         .tap_err(|err| warn!(id:display = &user.id, err:err = *err; "Failed to save user"))?;
 
-        let mut builder = sqlx::QueryBuilder::new(
-            "INSERT INTO interest (
+        if !user.interests.is_empty() {
+            let mut builder = sqlx::QueryBuilder::new(
+                "INSERT INTO interest (
                         id,
                         user_id,
                         name,
                         description
                     )",
-        );
+            );
 
-        builder.push_values(user.interests, |mut b, interest| {
-            b.push_bind(Uuid::new_v4())
-                .push_bind(&user.id)
-                .push_bind(interest.name)
-                .push_bind(interest.description);
-        });
+            builder.push_values(user.interests, |mut b, interest| {
+                b.push_bind(Uuid::new_v4())
+                    .push_bind(user.id)
+                    .push_bind(interest.name)
+                    .push_bind(interest.description);
+            });
 
-        builder.build().execute(&mut **tx).await.tap_err(
-            |err| warn!(id:display = &user.id, err:err = *err; "Failed to save user interests"),
-        )?;
+            builder.build().execute(&mut **tx).await.tap_err(
+                |err| warn!(id:display = &user.id, err:err = *err; "Failed to save user interests"),
+            )?;
+        }
 
         Ok(())
     }
+
+    async fn update(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        id: Uuid,
+        expected_versions: Option<&[i64]>,
+        patch: UserPatch,
+    ) -> Result<Option<i64>, Error> {
+        let version = sqlx::query_scalar!(
+            r#"
+            UPDATE users SET
+                first_name = COALESCE($3, first_name),
+                last_name = COALESCE($4, last_name),
+                birth_date = COALESCE($5, birth_date),
+                gender = COALESCE($6, gender),
+                city = COALESCE($7, city),
+                version = version + 1
+            WHERE id = $1 AND deleted_at IS NULL AND ($2::bigint[] IS NULL OR version = ANY($2))
+            RETURNING version
+            "#,
+            id,
+            expected_versions,
+            patch.first_name,
+            patch.last_name,
+            patch.birth_date,
+            patch.gender.as_ref().map(Into::<String>::into),
+            patch.city,
+        )
+        .fetch_optional(&mut **tx)
+        .await
+        .tap_err(|err| warn!(id:display = id, err:err = *err; "Failed to update user"))?;
+
+        let (Some(version), Some(interests)) = (version, patch.interests) else {
+            return Ok(version);
+        };
+
+        sqlx::query!("DELETE FROM interest WHERE user_id = $1", id)
+            .execute(&mut **tx)
+            .await
+            .tap_err(|err| warn!(id:display = id, err:err = *err; "Failed to delete user interests"))?;
+
+        if !interests.is_empty() {
+            let mut builder = sqlx::QueryBuilder::new(
+                "INSERT INTO interest (
+                        id,
+                        user_id,
+                        name,
+                        description
+                    )",
+            );
+
+            builder.push_values(interests, |mut b, interest| {
+                b.push_bind(Uuid::new_v4())
+                    .push_bind(id)
+                    .push_bind(interest.name)
+                    .push_bind(interest.description);
+            });
+
+            builder.build().execute(&mut **tx).await.tap_err(
+                |err| warn!(id:display = id, err:err = *err; "Failed to save user interests"),
+            )?;
+        }
+
+        Ok(Some(version))
+    }
+
+    async fn mark_deleted(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        id: Uuid,
+        deleted_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE users SET deleted_at = $2 WHERE id = $1 AND deleted_at IS NULL",
+            id,
+            deleted_at.naive_utc(),
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| warn!(id:display = id, err:err = *err; "Failed to mark user deleted"))
+        .unit()
+    }
+
+    async fn purge_deleted(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        before: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<u64, Error> {
+        // Lockout counters are keyed by login rather than user id, so they are erased explicitly
+        sqlx::query!(
+            r#"
+            WITH purged AS (
+                SELECT users.id FROM users
+                WHERE users.deleted_at < $1
+                ORDER BY users.deleted_at
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            ), attempts AS (
+                DELETE FROM login_attempts
+                WHERE key IN (
+                    SELECT 'login:' || auth.login FROM auth
+                    JOIN purged ON auth.user_id = purged.id
+                )
+            )
+            DELETE FROM users WHERE users.id IN (SELECT purged.id FROM purged)
+            "#,
+            before.naive_utc(),
+            limit,
+        )
+        .execute(&mut **tx)
+        .await
+        .tap_err(|err| warn!(err:err = *err; "Failed to purge deleted users"))
+        .map(|result| result.rows_affected())
+    }
+}
+
+/// Escapes `LIKE` wildcards so user input is matched literally as a prefix
+fn like_prefix(prefix: &str) -> String {
+    let mut pattern = String::with_capacity(prefix.len() + 1);
+    for symbol in prefix.chars() {
+        if matches!(symbol, '\\' | '%' | '_') {
+            pattern.push('\\');
+        }
+        pattern.push(symbol);
+    }
+    pattern.push('%');
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn appends_wildcard() {
+        assert_eq!(like_prefix("Ива"), "Ива%");
+        assert_eq!(like_prefix(""), "%");
+    }
+
+    #[test]
+    fn escapes_wildcards() {
+        assert_eq!(like_prefix("50%"), r"50\%%");
+        assert_eq!(like_prefix("a_b"), r"a\_b%");
+    }
+
+    #[test]
+    fn escapes_escape_character() {
+        assert_eq!(like_prefix(r"a\"), r"a\\%");
+        assert_eq!(like_prefix(r"\%"), r"\\\%%");
+    }
 }
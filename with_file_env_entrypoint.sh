@@ -24,5 +24,6 @@ file_env() {
 
 file_env "PG_USER"
 file_env "PG_PASS"
+file_env "JWT_SECRET"
 
 exec "$@"